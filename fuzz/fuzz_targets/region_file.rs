@@ -0,0 +1,19 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use mccompress::RegionFile;
+use std::io::Cursor;
+
+// parses arbitrary bytes as a region file and reads every chunk it claims to have, none of which
+// should ever panic, no matter how broken the header is
+fuzz_target!(|data: &[u8]| {
+    let mut region = match RegionFile::new(Cursor::new(data)) {
+        Ok(region) => region,
+        Err(_) => return,
+    };
+
+    let chunks: Vec<(u8, u8)> = region.chunks().collect();
+    for (x, z) in chunks {
+        let _ = region.junk_bytes(x, z);
+    }
+    let _ = region.verify();
+});
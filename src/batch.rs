@@ -0,0 +1,207 @@
+use crate::region::{Error, RecompressOptions, RecompressStats, RegionFile};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+
+#[cfg(feature = "parallel")]
+pub use threadpool::ThreadPool;
+
+/// Stands in for the thread pool when built without the `parallel` feature, it simply runs every
+/// job right away on the current thread
+#[cfg(not(feature = "parallel"))]
+pub struct ThreadPool;
+
+#[cfg(not(feature = "parallel"))]
+impl ThreadPool {
+    pub fn new(_threads: usize) -> ThreadPool {
+        ThreadPool
+    }
+
+    pub fn execute<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        job()
+    }
+
+    pub fn join(&self) {}
+}
+
+/// The combined result of recompressing a batch of region files
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// Statistics of all the files that were recompressed, added together
+    pub stats: RecompressStats,
+
+    /// The files that couldn't be recompressed and why, sorted by path
+    pub errors: Vec<(PathBuf, Error)>,
+}
+
+/// The amount of threads to use for `jobs`, which is the number of cpus for 0
+pub fn thread_count(jobs: usize) -> usize {
+    match jobs {
+        0 => std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1),
+        jobs => jobs,
+    }
+}
+
+/// Runs `job` on every one of `items`, `jobs` at a time.  A `jobs` of 0 uses one job per cpu,
+/// without the `parallel` feature the items are always done one by one.  The results are handed
+/// to `done` on the calling thread as they come in, in the order the jobs finish.
+pub fn run_jobs<I, T, F, D>(items: I, jobs: usize, job: F, mut done: D)
+where
+    I: IntoIterator,
+    I::Item: Send + 'static,
+    T: Send + 'static,
+    F: Fn(I::Item) -> T + Send + Sync + 'static,
+    D: FnMut(T),
+{
+    let pool = ThreadPool::new(thread_count(jobs));
+    let job = Arc::new(job);
+    let (tx, rx) = mpsc::channel();
+
+    for item in items {
+        let tx = tx.clone();
+        let job = job.clone();
+        pool.execute(move || {
+            // the receiver outlives the pool, so this can't fail
+            tx.send(job(item)).unwrap();
+        });
+
+        // pass on whatever is done already, which is every job so far without the thread pool
+        rx.try_iter().for_each(&mut done);
+    }
+    drop(tx);
+
+    rx.into_iter().for_each(done);
+}
+
+fn recompress_file(path: &Path, options: &RecompressOptions) -> Result<RecompressStats, Error> {
+    // files without any chunks aren't even opened for writing
+    if RegionFile::new(File::open(path)?)?.count_chunks() == 0 {
+        return Ok(RecompressStats::default());
+    }
+
+    let f = OpenOptions::new().write(true).read(true).open(path)?;
+    RegionFile::new(f)?.recompress_region(options)
+}
+
+/// Recompresses every file in `paths` in place, `jobs` at a time.  A `jobs` of 0 uses one job
+/// per cpu, without the `parallel` feature the files are always done one by one.  Files that
+/// fail are collected in the report instead of stopping the rest.
+pub fn recompress_paths<I>(paths: I, options: &RecompressOptions, jobs: usize) -> BatchReport
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    let options = *options;
+    let mut report = BatchReport::default();
+    run_jobs(
+        paths,
+        jobs,
+        move |path: PathBuf| {
+            let res = recompress_file(&path, &options);
+            (path, res)
+        },
+        |(path, res)| match res {
+            Ok(stats) => report.stats += stats,
+            Err(error) => report.errors.push((path, error)),
+        },
+    );
+    report.errors.sort_by(|a, b| a.0.cmp(&b.0));
+    report
+}
+
+#[test]
+fn test_recompress_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut paths = Vec::new();
+    for name in &["r.0.0.mca", "r.0.1.mca"] {
+        let path = dir.path().join(name);
+        std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+        paths.push(path);
+    }
+    let missing = dir.path().join("r.1.1.mca");
+    paths.push(missing.clone());
+
+    let options = RecompressOptions::new(flate2::Compression::best());
+    let single = recompress_file(&paths[0], &options).unwrap();
+    std::fs::copy("tests/data/r.0.0.mca", &paths[0]).unwrap();
+
+    let report = recompress_paths(paths, &options, 0);
+    assert_eq!(report.stats.bytes_before, single.bytes_before * 2);
+    assert_eq!(report.stats.bytes_after, single.bytes_after * 2);
+    assert_eq!(
+        report.stats.chunks_recompressed,
+        single.chunks_recompressed * 2
+    );
+    assert_eq!(report.errors.len(), 1);
+    assert_eq!(report.errors[0].0, missing);
+    assert!(matches!(report.errors[0].1, Error::Io(_)));
+
+    // both copies ended up the same as recompressing a single one
+    let once = std::fs::read(dir.path().join("r.0.0.mca")).unwrap();
+    assert_eq!(std::fs::read(dir.path().join("r.0.1.mca")).unwrap(), once);
+}
+
+#[test]
+fn test_run_jobs() {
+    let mut results = Vec::new();
+    run_jobs(1..=20, 4, |n: u32| n * n, |square| results.push(square));
+    results.sort();
+    assert_eq!(results, (1..=20).map(|n| n * n).collect::<Vec<u32>>());
+}
+
+#[test]
+fn test_recompress_empty_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::write(&path, vec![0; 8192]).unwrap();
+
+    // without write permission it can still be left alone
+    let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(&path, permissions).unwrap();
+
+    let options = RecompressOptions::new(flate2::Compression::best());
+    let report = recompress_paths(vec![path.clone()], &options, 1);
+    assert!(report.errors.is_empty());
+    assert_eq!(report.stats, RecompressStats::default());
+    assert_eq!(std::fs::read(&path).unwrap(), vec![0; 8192]);
+}
+
+#[cfg(not(feature = "parallel"))]
+#[test]
+fn test_sequential_pool() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // every job is done by the time execute returns
+    let pool = ThreadPool::new(4);
+    let done = Arc::new(AtomicUsize::new(0));
+    for i in 0..3 {
+        let counter = done.clone();
+        pool.execute(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(done.load(Ordering::SeqCst), i + 1);
+    }
+
+    // so a batch is recompressed on this thread, one file after the other
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    let options = RecompressOptions::new(flate2::Compression::best());
+    let report = recompress_paths(vec![path.clone()], &options, 4);
+    assert!(report.errors.is_empty());
+    assert_eq!(report.stats.chunks_recompressed, 381);
+
+    let mut expected =
+        RegionFile::from_bytes(std::fs::read("tests/data/r.0.0.mca").unwrap()).unwrap();
+    expected.recompress_region(&options).unwrap();
+    assert_eq!(
+        std::fs::read(&path).unwrap(),
+        expected.into_inner().into_inner()
+    );
+}
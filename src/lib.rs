@@ -0,0 +1,28 @@
+//! Tools for cleaning up and recompressing minecraft region files.
+//!
+//! Nothing in here prints anything or needs a logger or other global state to be set up first,
+//! [`recompress_paths`] for example simply returns its statistics.  That's left to the
+//! `mccompress` binary.
+//!
+//! ```
+//! use std::fs::File;
+//!
+//! let f = File::open("tests/data/r.0.0.mca").unwrap();
+//! let region = mccompress::RegionFile::new(f).unwrap();
+//!
+//! for (x, z) in region.chunks() {
+//!     println!("chunk {} {} was last modified at {:?}", x, z, region.get_chunk_timestamp(x, z));
+//! }
+//! ```
+
+pub mod batch;
+pub mod region;
+pub mod world;
+
+pub use batch::{recompress_paths, BatchReport};
+pub use region::{
+    parse_region_coords, ChunkEntry, ChunkIter, ChunkRange, ChunkSizes, CleanupStats, CompactOrder,
+    CompressionFormat, Error, InfoReport, RawChunkIter, RecompressOptions, RecompressStats,
+    RegionFile, SectorUsage,
+};
+pub use world::{region_for_chunk, World};
@@ -1,163 +1,3924 @@
 extern crate clap;
 extern crate walkdir;
 
-use clap::Clap;
+use clap::{Parser, Subcommand, ValueEnum};
+use filetime::FileTime;
 use flate2::Compression;
-use std::fs::OpenOptions;
-use std::path::PathBuf;
-use threadpool::ThreadPool;
+use fs2::FileExt;
+use globset::{Glob, GlobMatcher};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{debug, error, info, warn};
+use mccompress::batch::run_jobs;
+use mccompress::region;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
 use walkdir::{DirEntry, WalkDir};
 
-mod region;
-
-#[derive(Clap)]
+#[derive(Parser)]
 struct Opts {
+    // only print errors and warnings, like files with chunks that couldn't be read
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    // also print what happened to every single chunk
+    #[clap(short, long, global = true)]
+    verbose: bool,
+
     #[clap(subcommand)]
     subcmd: SubCommand,
 }
 
-#[derive(Clap)]
+#[derive(Subcommand)]
 enum SubCommand {
     Cleanup(CleanupOpts),
     Recompress(RecompressOpts),
+    Info(InfoOpts),
+    Junk(JunkOpts),
+    Verify(VerifyOpts),
+    Repair(RepairOpts),
+    Defrag(DefragOpts),
+    Extract(ExtractOpts),
+    Import(ImportOpts),
+    #[cfg(feature = "nbt")]
+    Prune(PruneOpts),
+    #[cfg(feature = "nbt")]
+    Entities(EntitiesOpts),
+}
+
+#[derive(Parser, Clone, Copy)]
+struct BackupOpts {
+    // copy every region file to <name>.bak before modifying it
+    #[clap(long)]
+    backup: bool,
+
+    // overwrite backups that already exist, and process files that are locked or belong to a world
+    // that is in use
+    #[clap(long)]
+    force: bool,
+}
+
+#[derive(Parser)]
+struct MccOpts {
+    // keep the .mcc files of chunks that are too large for their region file in this directory,
+    // instead of next to the region file
+    #[clap(long)]
+    mcc_dir: Option<PathBuf>,
 }
 
-#[derive(Clap)]
+#[derive(Parser)]
+struct SelectOpts {
+    // only process the region files of this dimension when walking a world
+    #[clap(long, value_enum, default_value = "all")]
+    dimension: Dimension,
+
+    // only process the region files whose path or name matches this glob, can be given more than
+    // once
+    #[clap(long, value_name = "PATTERN", value_parser = parse_glob)]
+    include: Vec<GlobMatcher>,
+
+    // skip the region files whose path or name matches this glob, even when they're included, can
+    // be given more than once
+    #[clap(long, value_name = "PATTERN", value_parser = parse_glob)]
+    exclude: Vec<GlobMatcher>,
+}
+
+#[derive(Parser)]
 struct CleanupOpts {
     // the files/folders that should be processed
     #[clap(required = true)]
     input: Vec<PathBuf>,
 
-    // the amount of jobs are allowed to run at the same time
-    #[clap(short, long, default_value = "16")]
-    jobs: usize,
+    #[clap(flatten)]
+    select: SelectOpts,
+
+    // the amount of jobs are allowed to run at the same time, 0 or leaving it out uses the number of cpus
+    #[clap(short, long, alias = "threads")]
+    jobs: Option<usize>,
+
+    // process the files one at a time in order, so the output is the same on every run
+    #[clap(long, conflicts_with = "jobs")]
+    sequential: bool,
+
+    // write the cleaned files to a mirrored tree in this directory instead of modifying the inputs
+    #[clap(short, long)]
+    output_dir: Option<PathBuf>,
+
+    // also zero the sectors that aren't used by any chunk, so the files compress better as a whole
+    #[clap(long)]
+    zero_fill: bool,
+
+    // only clean chunks that were modified after this unix timestamp
+    #[clap(long, value_name = "UNIX_TS", conflicts_with = "zero-fill")]
+    modified_after: Option<u32>,
+
+    // only clean the chunks in this rectangle of local chunk coordinates within each region
+    #[clap(long, value_name = "X1,Z1,X2,Z2", value_parser = parse_chunk_range, conflicts_with = "zero-fill")]
+    chunk_range: Option<region::ChunkRange>,
+
+    // keep the modification time the files had before processing them
+    #[clap(long)]
+    preserve_mtime: bool,
+
+    #[clap(flatten)]
+    backup: BackupOpts,
 }
 
-#[derive(Clap)]
+#[derive(Parser)]
 struct RecompressOpts {
     // the level of compression that should be used to recompress, 1 being the fastest, 9 being the best
-    #[clap(short, long, default_value = "5")]
-    level: u32,
+    // the names fast, best and default can be used as well, none stores the chunks uncompressed
+    #[clap(short, long, default_value = "5", value_parser = parse_compression)]
+    level: Level,
 
     // the files/folders that should be processed
     #[clap(required = true)]
     input: Vec<PathBuf>,
 
-    // the amount of jobs are allowed to run at the same time
-    #[clap(short, long, default_value = "16")]
-    jobs: usize,
+    #[clap(flatten)]
+    select: SelectOpts,
+
+    // the amount of jobs are allowed to run at the same time, 0 or leaving it out uses the number of cpus
+    #[clap(short, long, alias = "threads")]
+    jobs: Option<usize>,
+
+    // process the files one at a time in order, so the output is the same on every run
+    #[clap(long, conflicts_with = "jobs")]
+    sequential: bool,
+
+    // chunks that would shrink by less than this amount of bytes are left as they are
+    #[clap(long, default_value = "0")]
+    min_saving: usize,
+
+    // chunks with less compressed data than this amount of bytes aren't recompressed at all
+    #[clap(long, default_value = "0")]
+    min_chunk_bytes: usize,
+
+    // only recompress chunks that were modified after this unix timestamp
+    #[clap(long, value_name = "UNIX_TS")]
+    modified_after: Option<u32>,
+
+    // only recompress the chunks in this rectangle of local chunk coordinates within each region
+    #[clap(long, value_name = "X1,Z1,X2,Z2", value_parser = parse_chunk_range)]
+    chunk_range: Option<region::ChunkRange>,
+
+    // leave files that would take up more space after recompressing as they were
+    #[clap(long)]
+    no_grow: bool,
+
+    // only recompress the chunks that have junk after their data, leaving the rest as they are
+    #[clap(long, conflicts_with_all = &["safe", "output-dir"])]
+    only_junk: bool,
+
+    // store chunks uncompressed when compressing them doesn't make them any smaller
+    #[clap(long)]
+    allow_store: bool,
+
+    // convert every chunk to this format while recompressing it, instead of keeping its own.  Only
+    // uncompressed goes together with --level none
+    #[clap(long, value_enum, conflicts_with = "allow-store")]
+    to_format: Option<TargetFormat>,
+
+    // try a couple of extra compression levels on every chunk and keep whichever is smallest
+    #[clap(long)]
+    adaptive: bool,
+
+    // set the timestamp of every recompressed chunk to the current time
+    #[clap(long)]
+    touch: bool,
+
+    // decompress every recompressed chunk again and check it matches the original
+    #[clap(long)]
+    verify: bool,
+
+    // check every chunk of a file can be read before recompressing it, and leave files with chunks
+    // that can't be as they are
+    #[clap(long)]
+    verify_first: bool,
+
+    // compare a digest of all the decompressed chunks of every file before and after recompressing
+    // it, and treat any difference as an error
+    #[clap(long)]
+    verify_digest: bool,
+
+    // write the recompressed region to a temporary file and atomically move it over the original
+    #[clap(long, alias = "atomic")]
+    safe: bool,
+
+    // write the recompressed files to a mirrored tree in this directory instead of modifying the inputs
+    #[clap(short, long)]
+    output_dir: Option<PathBuf>,
+
+    // keep the modification time the files had before processing them
+    #[clap(long)]
+    preserve_mtime: bool,
+
+    // how the statistics of each file should be printed
+    #[clap(long, value_enum, default_value = "text")]
+    format: Format,
+
+    #[clap(flatten)]
+    backup: BackupOpts,
+
+    #[clap(flatten)]
+    mcc: MccOpts,
+}
+
+#[derive(Parser)]
+struct InfoOpts {
+    // the files/folders that should be inspected
+    #[clap(required = true)]
+    input: Vec<PathBuf>,
+
+    // how the statistics should be printed, csv always lists every chunk as with --chunks
+    #[clap(long, value_enum, default_value = "text")]
+    format: Format,
+
+    // print the header entry of every chunk instead of a summary of each file
+    #[clap(long)]
+    chunks: bool,
+
+    // print a hexdump of the offset and timestamp tables of each file, for bug reports
+    #[clap(long, conflicts_with = "chunks")]
+    dump_header: bool,
 }
 
-fn is_mca(entry: &DirEntry) -> bool {
-    let file_type = entry.file_type();
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| file_type.is_dir() || (file_type.is_file() && s.ends_with(".mca")))
-        .unwrap_or(false)
+#[derive(Parser)]
+struct JunkOpts {
+    // the files/folders that should be checked for junk
+    #[clap(required = true)]
+    input: Vec<PathBuf>,
 }
 
-fn cleanup_handle(subopts: &CleanupOpts) {
-    let pool = ThreadPool::new(subopts.jobs);
+#[derive(Parser)]
+struct VerifyOpts {
+    // the files/folders that should be checked for unreadable chunks
+    #[clap(required = true)]
+    input: Vec<PathBuf>,
+
+    // only check the chunks in this rectangle of local chunk coordinates within each region
+    #[clap(long, value_name = "X1,Z1,X2,Z2", value_parser = parse_chunk_range)]
+    chunk_range: Option<region::ChunkRange>,
 
-    let cleanup = |file: &DirEntry| {
-        let res = || -> Result<usize, region::Error> {
-            let f = OpenOptions::new()
-                .write(true)
-                .read(true)
-                .open(file.path())?;
-            let mut region = region::RegionFile::new(f)?;
+    #[clap(flatten)]
+    mcc: MccOpts,
+}
+
+#[derive(Parser)]
+struct RepairOpts {
+    // the files/folders whose unrecoverable chunks should be deleted
+    #[clap(required = true)]
+    input: Vec<PathBuf>,
+
+    #[clap(flatten)]
+    backup: BackupOpts,
+
+    #[clap(flatten)]
+    mcc: MccOpts,
+}
+
+#[derive(Parser)]
+struct DefragOpts {
+    // the files/folders whose chunks should be packed together again, without recompressing them
+    #[clap(required = true)]
+    input: Vec<PathBuf>,
+
+    #[clap(flatten)]
+    backup: BackupOpts,
+
+    #[clap(flatten)]
+    mcc: MccOpts,
+}
+
+#[derive(Parser)]
+struct ExtractOpts {
+    // the region file to extract the chunk from
+    input: PathBuf,
+
+    // the coordinates of the chunk within the region
+    #[clap(long, required = true, number_of_values = 2, value_names = &["X", "Z"], value_parser = clap::value_parser!(u8).range(0..32))]
+    chunk: Vec<u8>,
+
+    // the file the nbt of the chunk should be written to
+    #[clap(long)]
+    out: PathBuf,
+
+    // write the nbt as it is instead of wrapping it in gzip, like standalone .nbt files are
+    #[clap(long)]
+    raw: bool,
+
+    #[clap(flatten)]
+    mcc: MccOpts,
+}
+
+#[derive(Parser)]
+struct ImportOpts {
+    // the region file to import the chunk into
+    region: PathBuf,
+
+    // the coordinates of the chunk within the region
+    #[clap(long, required = true, number_of_values = 2, value_names = &["X", "Z"], value_parser = clap::value_parser!(u8).range(0..32))]
+    chunk: Vec<u8>,
+
+    // the nbt file to import, either raw or gzip compressed
+    #[clap(long = "in")]
+    input: PathBuf,
+
+    // the modification time to give the chunk, defaults to now
+    #[clap(long)]
+    timestamp: Option<u32>,
+
+    #[clap(flatten)]
+    mcc: MccOpts,
+}
+
+#[cfg(feature = "nbt")]
+#[derive(Parser)]
+struct PruneOpts {
+    // the files/folders that should be pruned
+    #[clap(required = true)]
+    input: Vec<PathBuf>,
+
+    // chunks that players have spent fewer ticks than this in are deleted
+    #[clap(long, default_value = "1")]
+    inhabited_below: i64,
+
+    #[clap(flatten)]
+    backup: BackupOpts,
+}
+
+#[cfg(feature = "nbt")]
+#[derive(Parser)]
+struct EntitiesOpts {
+    // the files/folders that should be searched for chunks with a lot of (block) entities
+    #[clap(required = true)]
+    input: Vec<PathBuf>,
+
+    // the amount of chunks that should be listed
+    #[clap(long, default_value = "10")]
+    top: usize,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum Dimension {
+    Overworld,
+    Nether,
+    End,
+    All,
+}
+
+impl Dimension {
+    /// Figures out which dimension a region file belongs to by the folders of a world it is in,
+    /// `None` for the custom dimensions of datapacks in the `dimensions` folder
+    fn of(path: &Path) -> Option<Dimension> {
+        let dirs = path.parent()?.components().rev();
+        for dir in dirs.map(|dir| dir.as_os_str()) {
+            if dir == "DIM-1" {
+                return Some(Dimension::Nether);
+            } else if dir == "DIM1" {
+                return Some(Dimension::End);
+            } else if dir == "dimensions" {
+                return None;
+            }
+        }
+        Some(Dimension::Overworld)
+    }
+
+    /// Whether the region file at `path` should be processed
+    fn contains(self, path: &Path) -> bool {
+        self == Dimension::All || Dimension::of(path) == Some(self)
+    }
+}
 
-            region.clean_junk()
+impl SelectOpts {
+    /// Whether the region file at `path` should be processed.  It has to be in the dimension and
+    /// match one of the includes if there are any, the excludes win over both
+    fn contains(&self, path: &Path) -> bool {
+        let matches = |globs: &[GlobMatcher]| {
+            globs.iter().any(|glob| {
+                glob.is_match(path) || path.file_name().is_some_and(|name| glob.is_match(name))
+            })
         };
+        self.dimension.contains(path)
+            && (self.include.is_empty() || matches(&self.include))
+            && !matches(&self.exclude)
+    }
+}
 
-        match res() {
-            Ok(_res) => {
-                println!("Proccessed {}", file.path().display());
+/// The compression formats chunks can be converted to
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum TargetFormat {
+    Gzip,
+    Zlib,
+    Uncompressed,
+    Zstd,
+}
+
+impl From<TargetFormat> for region::CompressionFormat {
+    fn from(format: TargetFormat) -> region::CompressionFormat {
+        match format {
+            TargetFormat::Gzip => region::CompressionFormat::Gzip,
+            TargetFormat::Zlib => region::CompressionFormat::Zlib,
+            TargetFormat::Uncompressed => region::CompressionFormat::Uncompressed,
+            TargetFormat::Zstd => region::CompressionFormat::Zstd,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+impl BackupOpts {
+    /// Copies `path` to `<path>.bak` if backups are enabled, returning the path of the backup.
+    /// An existing backup is never overwritten unless forced
+    fn create(&self, path: &Path) -> io::Result<Option<PathBuf>> {
+        if !self.backup {
+            return Ok(None);
+        }
+
+        let mut name = path.as_os_str().to_owned();
+        name.push(".bak");
+        let backup = PathBuf::from(name);
+
+        if !self.force && backup.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} already exists, use --force to overwrite it",
+                    backup.display()
+                ),
+            ));
+        }
+
+        fs::copy(path, &backup)?;
+        Ok(Some(backup))
+    }
+}
+
+/// Compacts a region file into a temporary file next to it, which then replaces the original.
+/// The chunks are recompressed along the way when `options` are given, and chunks in a `.mcc`
+/// file (in `mcc_dir` if given) that fit in the region again are moved back in.  The original is
+/// left untouched if anything goes wrong along the way
+fn compact_atomic(
+    path: &Path,
+    options: Option<&region::RecompressOptions>,
+    mcc_dir: Option<&Path>,
+) -> Result<region::RecompressStats, region::Error> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let f = File::open(path)?;
+    let permissions = f.metadata()?.permissions();
+    let mut region = region::RegionFile::new(f)?;
+    region.set_region_path_with_mcc_dir(path, mcc_dir);
+    let external = region.external_chunks()?;
+
+    // the temporary file is removed again when it's dropped without being persisted
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    let res = {
+        let mut out = BufWriter::new(tmp.as_file_mut());
+        let res = region.compact(&mut out, options, region::CompactOrder::Index)?;
+        out.flush()?;
+        res
+    };
+    tmp.as_file().sync_all()?;
+    fs::set_permissions(tmp.path(), permissions)?;
+    tmp.persist(path).map_err(|e| e.error)?;
+
+    // only now that the new file is in place, the `.mcc` files of the chunks it took in can go
+    let mut compacted = region::RegionFile::new(File::open(path)?)?;
+    let still_external = compacted.external_chunks()?;
+    for (x, z) in external {
+        if !still_external.contains(&(x, z)) {
+            if let Some(mcc) = region.external_chunk_path(x, z) {
+                fs::remove_file(mcc)?;
             }
-            Err(error) => {
-                println!(
-                    "Error while processing {}: {:?}",
-                    file.path().display(),
-                    error
-                );
+        }
+    }
+
+    Ok(res)
+}
+
+/// Recompresses and compacts a region file into `dest`, leaving the source untouched.  When the
+/// region would have grown `dest` becomes a plain copy of the source instead.  `dest` only shows
+/// up once it is complete, so an error never leaves a half written region behind.  Chunks in a
+/// `.mcc` file (in `mcc_dir` if given) are moved into `dest` when they fit, the `.mcc` files of
+/// the others are copied next to it
+fn recompress_to(
+    src: &Path,
+    dest: &Path,
+    options: &region::RecompressOptions,
+    mcc_dir: Option<&Path>,
+) -> Result<region::RecompressStats, region::Error> {
+    let f = File::open(src)?;
+    let permissions = f.metadata()?.permissions();
+    let mut region = region::RegionFile::new(f)?;
+    region.set_region_path_with_mcc_dir(src, mcc_dir);
+
+    let dir = match dest.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+
+    // the temporary file is removed again when it's dropped without being persisted
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    let res = {
+        let mut out = BufWriter::new(tmp.as_file_mut());
+        match region.compact(&mut out, Some(options), region::CompactOrder::Index) {
+            Err(error @ region::Error::RegionGrew { .. }) => Err(error),
+            res => {
+                let res = res?;
+                out.flush()?;
+                Ok(res)
             }
-        };
+        }
     };
+    if res.is_err() {
+        fs::copy(src, tmp.path())?;
+    }
+    tmp.as_file().sync_all()?;
+    fs::set_permissions(tmp.path(), permissions)?;
+    tmp.persist(dest).map_err(|e| e.error)?;
 
-    for dir in &subopts.input {
-        WalkDir::new(dir)
-            .into_iter()
-            .filter_entry(|e| is_mca(e))
-            .filter_map(|v| v.ok())
-            .for_each(|x| {
-                let metadata = x.metadata().unwrap();
-                if metadata.is_file() && metadata.len() > 0 {
-                    pool.execute(move || cleanup(&x));
+    // the game looks for the `.mcc` files next to the region file.  The ones that are missing
+    // were already counted as failed chunks.
+    let mut written = region::RegionFile::new(File::open(dest)?)?;
+    written.set_region_path(dest);
+    for (x, z) in written.external_chunks()? {
+        if let (Some(from), Some(to)) = (
+            region.external_chunk_path(x, z),
+            written.external_chunk_path(x, z),
+        ) {
+            match fs::copy(from, to) {
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                res => {
+                    res?;
                 }
-            });
+            }
+        }
     }
 
-    pool.join();
+    res
 }
 
-fn recompress_handle(subopts: &RecompressOpts) {
-    let pool = ThreadPool::new(subopts.jobs);
+/// Recompresses a whole region file read from `input` in memory, as the region needs to be
+/// seekable, and writes the result to `output`.  Gives the same bytes as recompressing a file in
+/// place would.
+fn recompress_stream<R: io::Read, W: Write>(
+    mut input: R,
+    mut output: W,
+    options: &region::RecompressOptions,
+    only_junk: bool,
+    verify_digest: bool,
+) -> Result<region::RecompressStats, region::Error> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
 
-    for dir in &subopts.input {
-        WalkDir::new(dir)
-            .into_iter()
-            .filter_entry(|e| is_mca(e))
-            .filter_map(|v| v.ok())
-            .for_each(|file| {
-                let metadata = file.metadata().unwrap();
-                if metadata.is_file() && metadata.len() > 0 {
-                    let level = subopts.level;
-                    pool.execute(move || {
-                        let res = || -> Result<usize, region::Error> {
-                            let f = OpenOptions::new()
-                                .write(true)
-                                .read(true)
-                                .open(file.path())?;
-                            let mut region = region::RegionFile::new(f)?;
-
-                            let res = region.recompress_region(Compression::new(level));
-
-                            match res {
-                                Ok(r) => Ok(r.1),
-                                Err(error) => Err(error),
-                            }
-                        };
-
-                        match res() {
-                            Ok(_res) => {
-                                println!("Processed {}", file.path().display());
-                            }
-                            Err(error) => {
-                                println!(
-                                    "Error while processing {}: {:?}",
-                                    file.path().display(),
-                                    error
-                                );
-                            }
-                        };
-                    });
-                }
-            });
+    let mut region = region::RegionFile::new(io::Cursor::new(data))?;
+    let digest = match verify_digest {
+        true => Some(region.content_digest()?),
+        false => None,
+    };
+    let res = region.recompress_region_filtered(
+        options,
+        |region, x, z| Ok(!only_junk || has_junk(region, x, z)),
+        |_, _, _, _| {},
+    );
+    if let (Ok(_), Some(before)) = (&res, digest) {
+        check_digest(before, region.content_digest()?)?;
+    }
+
+    // a region that would have grown was put back as it was, which still has to be passed on
+    if let Ok(_) | Err(region::Error::RegionGrew { .. }) = res {
+        output.write_all(region.into_inner().get_ref())?;
+        output.flush()?;
     }
 
-    pool.join();
+    res
 }
 
-fn main() {
-    let opts: Opts = Opts::parse();
+/// The content digest of the region file at `path`, including the chunks in `.mcc` files
+fn file_digest(path: &Path) -> Result<[u8; 32], region::Error> {
+    let mut region = region::RegionFile::new(File::open(path)?)?;
+    region.set_region_path(path);
+    region.content_digest()
+}
 
-    match opts.subcmd {
-        SubCommand::Cleanup(subopts) => {
-            cleanup_handle(&subopts);
+/// Makes sure the chunks still decompress to the same data, for `--verify-digest`
+fn check_digest(before: [u8; 32], after: [u8; 32]) -> Result<(), region::Error> {
+    if before != after {
+        return Err(region::Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the chunks don't decompress to the same data anymore after recompressing",
+        )));
+    }
+    Ok(())
+}
+
+/// Whether a chunk has any junk after its data, for `--only-junk`.  Chunks that can't be read are
+/// recompressed anyway, so they show up as failed
+fn has_junk<R: io::Read + io::Seek>(region: &mut region::RegionFile<R>, x: u8, z: u8) -> bool {
+    region.junk_bytes(x, z).map_or(true, |junk| junk > 0)
+}
+
+/// Figures out where the output for `file` should go, mirroring its location relative to the
+/// walked `root` inside of `output_dir`
+fn destination(root: &Path, file: &Path, output_dir: &Path) -> PathBuf {
+    match file.strip_prefix(root) {
+        Ok(relative) if !relative.as_os_str().is_empty() => output_dir.join(relative),
+        // the root was the file itself
+        _ => output_dir.join(file.file_name().unwrap_or_default()),
+    }
+}
+
+/// How recompressed chunks should be compressed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Level {
+    /// Deflate with this level, keeping the format every chunk already had
+    Deflate(Compression),
+    /// Store the chunks uncompressed, as compression type 3
+    Store,
+}
+
+impl Level {
+    /// The options to recompress with at this level, before any of the other flags are applied
+    fn options(self) -> region::RecompressOptions {
+        match self {
+            Level::Deflate(level) => region::RecompressOptions::new(level),
+            Level::Store => {
+                let mut options = region::RecompressOptions::new(Compression::none());
+                options.target_format = Some(region::CompressionFormat::Uncompressed);
+                options
+            }
+        }
+    }
+}
+
+/// Parses a compression level, either as a number between 0 and 9 or by the name of a preset.
+/// `none` stores the chunks without compressing them at all, which isn't the same as level 0:
+/// that still makes zlib streams, just out of stored deflate blocks.
+fn parse_compression(s: &str) -> Result<Level, String> {
+    match s {
+        "fast" => Ok(Level::Deflate(Compression::fast())),
+        "best" => Ok(Level::Deflate(Compression::best())),
+        "default" => Ok(Level::Deflate(Compression::default())),
+        "none" => Ok(Level::Store),
+        _ => match s.parse::<u32>() {
+            Ok(level) if level <= 9 => Ok(Level::Deflate(Compression::new(level))),
+            _ => Err(format!(
+                "invalid compression level '{}', expected 0-9, none, fast, best or default",
+                s
+            )),
+        },
+    }
+}
+
+/// Parses the corners of a `--chunk-range`, as the local coordinates of two chunks
+fn parse_chunk_range(s: &str) -> Result<region::ChunkRange, String> {
+    let invalid = || {
+        format!(
+            "invalid chunk range '{}', expected x1,z1,x2,z2 with coordinates from 0 to 31",
+            s
+        )
+    };
+    let coords = s
+        .split(',')
+        .map(|v| v.trim().parse::<u8>())
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| invalid())?;
+    match coords[..] {
+        [x1, z1, x2, z2] => region::ChunkRange::new(x1.min(x2), z1.min(z2), x1.max(x2), z1.max(z2))
+            .ok_or_else(invalid),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses a glob for `--include` and `--exclude`, a `*` matches across directories as well
+fn parse_glob(s: &str) -> Result<GlobMatcher, String> {
+    Glob::new(s)
+        .map(|glob| glob.compile_matcher())
+        .map_err(|error| error.to_string())
+}
+
+/// The amount of threads to use, falls back to the number of cpus if not explicitly specified.
+/// Sequential runs always use a single thread
+fn thread_count(jobs: Option<usize>, sequential: bool) -> usize {
+    if sequential {
+        return 1;
+    }
+
+    mccompress::batch::thread_count(jobs.unwrap_or(0))
+}
+
+/// Runs `f`, which writes to `dest`, and gives `dest` the modification time `src` had beforehand
+/// when `preserve` is set
+fn preserving_mtime<T, F>(src: &Path, dest: &Path, preserve: bool, f: F) -> Result<T, region::Error>
+where
+    F: FnOnce() -> Result<T, region::Error>,
+{
+    let mtime = if preserve {
+        Some(FileTime::from_last_modification_time(&fs::metadata(src)?))
+    } else {
+        None
+    };
+
+    let res = f()?;
+
+    if let Some(mtime) = mtime {
+        filetime::set_file_mtime(dest, mtime)?;
+    }
+
+    Ok(res)
+}
+
+/// Region files are either `.mca` files, or `.mcr` files in worlds from before the Anvil format
+fn is_region_file(entry: &DirEntry) -> bool {
+    entry.file_type().is_file()
+        && entry
+            .path()
+            .extension()
+            .is_some_and(|ext| ext == "mca" || ext == "mcr")
+}
+
+/// Checks that an entry is a non-empty file, entries that can no longer be inspected (because they
+/// vanished or we lack the permissions) are reported and skipped, and counted in `failures`
+fn is_nonempty_file(entry: &DirEntry, failures: &mut usize) -> bool {
+    match entry.metadata() {
+        Ok(metadata) => metadata.is_file() && metadata.len() > 0,
+        Err(error) => {
+            warn!("Skipping {}: {}", entry.path().display(), error);
+            *failures += 1;
+            false
+        }
+    }
+}
+
+/// Hands back the entry if walking the directories got to it, otherwise the error is reported with
+/// the path that couldn't be read, so it's clear which part of the inputs was skipped
+fn walked(input: &Path, entry: walkdir::Result<DirEntry>) -> Option<DirEntry> {
+    match entry {
+        Ok(entry) => Some(entry),
+        Err(error) => {
+            let path = error.path().unwrap_or(input).display().to_string();
+            match error.io_error() {
+                Some(io_error) => warn!("Skipping {}: {}", path, io_error),
+                None => warn!("Skipping {}: {}", path, error),
+            }
+            None
+        }
+    }
+}
+
+/// Whether another process holds an fcntl lock on a file.  This is the kind of lock Java takes, and
+/// so the one a running server holds, which never conflicts with the flock locks fs2 takes.  A
+/// shared lock is enough to find out, as the server's lock is exclusive, and it only needs the file
+/// to be open for reading
+#[cfg(unix)]
+fn fcntl_locked(f: &File) -> bool {
+    use rustix::fs::{fcntl_lock, FlockOperation};
+    use rustix::io::Errno;
+
+    match fcntl_lock(f, FlockOperation::NonBlockingLockShared) {
+        Ok(()) => {
+            // no other locks of this process are on the file, so this only drops the probe
+            let _ = fcntl_lock(f, FlockOperation::NonBlockingUnlock);
+            false
         }
-        SubCommand::Recompress(subopts) => {
-            recompress_handle(&subopts);
+        Err(error) => error == Errno::AGAIN || error == Errno::ACCESS,
+    }
+}
+
+/// Locks on other platforms are mandatory, so taking the lock with fs2 already catches them
+#[cfg(not(unix))]
+fn fcntl_locked(_: &File) -> bool {
+    false
+}
+
+/// Takes an exclusive advisory lock on a region file, so it isn't processed while another process
+/// holds a lock on it.  The lock is released once the returned file is dropped, nothing is locked
+/// when forced
+fn lock_region(path: &Path, force: bool) -> io::Result<Option<File>> {
+    if force {
+        return Ok(None);
+    }
+
+    let f = File::open(path)?;
+    if fcntl_locked(&f) || f.try_lock_exclusive().is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "it is locked by another process, use --force to process it anyway",
+        ));
+    }
+    Ok(Some(f))
+}
+
+/// Checks whether a running server holds the lock on the session.lock file of the world an input
+/// is in, which is looked for in the input itself and every directory above it.  That way single
+/// dimensions and region files of a world in use are caught too
+fn world_in_use(input: &Path) -> bool {
+    let input = input.canonicalize().unwrap_or_else(|_| input.to_path_buf());
+    input
+        .ancestors()
+        .any(|dir| match File::open(dir.join("session.lock")) {
+            // our own lock is released again right away when the file is dropped
+            Ok(f) => fcntl_locked(&f) || f.try_lock_exclusive().is_err(),
+            Err(_) => false,
+        })
+}
+
+/// Leaves out the inputs that are worlds in use by a running server, unless forced
+fn unlocked_inputs(inputs: &[PathBuf], force: bool) -> Vec<PathBuf> {
+    inputs
+        .iter()
+        .filter(|input| {
+            if !force && world_in_use(input) {
+                warn!(
+                    "Skipping {}: the world is in use, use --force to process it anyway",
+                    input.display()
+                );
+                return false;
+            }
+            true
+        })
+        .cloned()
+        .collect()
+}
+
+/// Walks all the inputs and returns every region file that should be processed, together with
+/// the input it was found in.  Every directory is descended into, only the files are filtered.
+/// The files are sorted by path, so they're always processed in the same order.  Every part of the
+/// inputs that couldn't be walked is added to `failures`
+fn region_files<'a>(inputs: &'a [PathBuf], failures: &mut usize) -> Vec<(&'a Path, DirEntry)> {
+    let mut files: Vec<(&Path, DirEntry)> = Vec::new();
+    for dir in inputs {
+        for entry in WalkDir::new(dir) {
+            match walked(dir, entry) {
+                Some(entry) if is_region_file(&entry) && is_nonempty_file(&entry, failures) => {
+                    files.push((dir.as_path(), entry))
+                }
+                Some(_) => {}
+                None => *failures += 1,
+            }
         }
     }
+    files.sort_by(|(_, a), (_, b)| a.path().cmp(b.path()));
+    files
+}
+
+/// The region files in all the inputs that were selected, collected up front so the total amount
+/// of files is known
+fn selected_files<'a>(
+    inputs: &'a [PathBuf],
+    select: &SelectOpts,
+    failures: &mut usize,
+) -> Vec<(&'a Path, DirEntry)> {
+    let mut files = region_files(inputs, failures);
+    files.retain(|(_, file)| select.contains(file.path()));
+    files
+}
+
+/// Pairs every file with the path it should be written to instead when there's an output
+/// directory, mirroring where it is under its input in there
+fn with_destinations(
+    files: Vec<(&Path, DirEntry)>,
+    output_dir: Option<&Path>,
+) -> Vec<(DirEntry, Option<PathBuf>)> {
+    files
+        .into_iter()
+        .map(|(root, file)| {
+            let dest = output_dir.map(|dir| destination(root, file.path(), dir));
+            (file, dest)
+        })
+        .collect()
+}
+
+/// A progress bar over all the files that are going to be processed, which is only drawn when
+/// stderr is a terminal
+fn progress_bar(files: usize) -> Arc<ProgressBar> {
+    let target = if io::stderr().is_terminal() {
+        ProgressDrawTarget::stderr()
+    } else {
+        ProgressDrawTarget::hidden()
+    };
+    let progress = ProgressBar::with_draw_target(Some(files as u64), target);
+    if let Ok(style) = ProgressStyle::with_template("{wide_bar} {pos}/{len} files, eta {eta}") {
+        progress.set_style(style);
+    }
+    Arc::new(progress)
+}
+
+/// What was done to the region files in a single directory, like one dimension of a world
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct DirSummary {
+    files: usize,
+    chunks: usize,
+    /// Negative when the files grew
+    bytes_saved: i64,
+}
+
+/// The summaries of every directory that had files processed, shared between the jobs
+type Summaries = Arc<Mutex<BTreeMap<PathBuf, DirSummary>>>;
+
+/// Whether the region file at `path` has no chunks at all, which leaves nothing to do for it.  Only
+/// the header is read, the file isn't opened for writing.  Files that can't be read count as not
+/// empty, so the error gets reported when processing them.
+fn region_is_empty(path: &Path) -> bool {
+    File::open(path)
+        .map_err(region::Error::from)
+        .and_then(region::RegionFile::new)
+        .is_ok_and(|region| region.count_chunks() == 0)
+}
+
+/// Adds a processed file to the summary of the directory it is in
+fn add_to_summary(summaries: &Summaries, file: &Path, chunks: usize, bytes_saved: i64) {
+    let dir = file.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+    let mut summaries = summaries.lock().unwrap();
+    let summary = summaries.entry(dir).or_default();
+    summary.files += 1;
+    summary.chunks += chunks;
+    summary.bytes_saved += bytes_saved;
+}
+
+/// Logs the summary of every directory once all the jobs are done, and hands them back
+fn report_summaries(summaries: Summaries) -> BTreeMap<PathBuf, DirSummary> {
+    let summaries = std::mem::take(&mut *summaries.lock().unwrap());
+    for (dir, summary) in &summaries {
+        info!(
+            "{}: {} files, {} chunks, {} bytes saved",
+            dir.display(),
+            summary.files,
+            summary.chunks,
+            summary.bytes_saved
+        );
+    }
+    summaries
+}
+
+fn cleanup_handle(subopts: &CleanupOpts) -> (BTreeMap<PathBuf, DirSummary>, usize) {
+    let backup = subopts.backup;
+    let preserve_mtime = subopts.preserve_mtime;
+    let zero_fill = subopts.zero_fill;
+    let modified_after = subopts.modified_after;
+    let chunk_range = subopts.chunk_range.unwrap_or(region::ChunkRange::ALL);
+    let summaries = Summaries::default();
+    let summary = summaries.clone();
+    let cleanup = move |(file, dest): (DirEntry, Option<PathBuf>)| {
+        if dest.is_none() && region_is_empty(file.path()) {
+            debug!("Skipping {}, it has no chunks", file.path().display());
+            add_to_summary(&summary, file.path(), 0, 0);
+            return false;
+        }
+
+        let _lock = match lock_region(file.path(), backup.force) {
+            Ok(lock) => lock,
+            Err(error) => {
+                warn!("Skipping {}: {}", file.path().display(), error);
+                return false;
+            }
+        };
+
+        let res = || -> Result<(usize, usize, Option<usize>), region::Error> {
+            backup.create(file.path())?;
+
+            // when writing elsewhere we simply clean a copy of the file
+            let path = match &dest {
+                Some(dest) => {
+                    if let Some(parent) = dest.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::copy(file.path(), dest)?;
+                    dest.as_path()
+                }
+                None => file.path(),
+            };
+
+            preserving_mtime(file.path(), path, preserve_mtime, || {
+                let f = OpenOptions::new().write(true).read(true).open(path)?;
+                let mut region = region::RegionFile::new(f)?;
+
+                let (cleaned, junky) = if zero_fill {
+                    (region.zero_fill()?, None)
+                } else {
+                    let stats = region.clean_junk_in(chunk_range, modified_after)?;
+                    (stats.bytes_cleaned, Some(stats.chunks_cleaned))
+                };
+                Ok((region.count_chunks(), cleaned, junky))
+            })
+        };
+
+        match res() {
+            Ok((chunks, cleaned, junky)) => {
+                match junky {
+                    Some(junky) => info!(
+                        "Proccessed {}, {} chunks had junk in them",
+                        file.path().display(),
+                        junky
+                    ),
+                    None => info!("Proccessed {}", file.path().display()),
+                }
+                add_to_summary(&summary, file.path(), chunks, cleaned as i64);
+                false
+            }
+            Err(error) => {
+                error!(
+                    "Error while processing {}: {:?}",
+                    file.path().display(),
+                    error
+                );
+                true
+            }
+        }
+    };
+
+    let mut failures = 0;
+    let inputs = unlocked_inputs(&subopts.input, backup.force);
+    let files = with_destinations(
+        selected_files(&inputs, &subopts.select, &mut failures),
+        subopts.output_dir.as_deref(),
+    );
+    let progress = progress_bar(files.len());
+    run_jobs(
+        files,
+        thread_count(subopts.jobs, subopts.sequential),
+        cleanup,
+        |failed| {
+            progress.inc(1);
+            failures += failed as usize;
+        },
+    );
+    progress.finish_and_clear();
+    (report_summaries(summaries), failures)
+}
+
+/// The options every file is recompressed with.  `--level none` already decides the format, so
+/// asking for another one on top of that is refused rather than quietly compressing after all
+fn recompress_options(subopts: &RecompressOpts) -> Result<region::RecompressOptions, String> {
+    if let (Level::Store, Some(format)) = (subopts.level, subopts.to_format) {
+        if format != TargetFormat::Uncompressed {
+            let name = format
+                .to_possible_value()
+                .map_or("", |value| value.get_name());
+            return Err(format!(
+                "--level none stores the chunks uncompressed, it can't be combined with --to-format {}",
+                name
+            ));
+        }
+    }
+
+    let mut options = subopts.level.options();
+    options.min_saving = subopts.min_saving;
+    options.min_chunk_bytes = subopts.min_chunk_bytes;
+    options.modified_after = subopts.modified_after;
+    options.chunk_range = subopts.chunk_range;
+    options.allow_store = subopts.allow_store;
+    if let Some(format) = subopts.to_format {
+        options.target_format = Some(format.into());
+    }
+    options.adaptive = subopts.adaptive;
+    options.touch = subopts.touch;
+    options.verify = subopts.verify;
+    options.discard_if_grown = subopts.no_grow;
+    options.continue_on_error = true;
+    Ok(options)
+}
+
+/// Checks every chunk of a region file before recompressing it with `--verify-first`, telling why
+/// the file should be left alone when it should
+fn verify_first_failure(path: &Path) -> Option<String> {
+    match verify_report(path, None, region::ChunkRange::ALL) {
+        Ok((_, failed, _)) if failed.is_empty() => None,
+        Ok((_, failed, _)) => Some(format!(
+            "{} chunks failed to verify: {}",
+            failed.len(),
+            format_chunks(&failed)
+        )),
+        Err(error) => Some(format!("it couldn't be verified: {:?}", error)),
+    }
+}
+
+fn recompress_handle(subopts: &RecompressOpts) -> (BTreeMap<PathBuf, DirSummary>, usize) {
+    let options = match recompress_options(subopts) {
+        Ok(options) => options,
+        Err(error) => {
+            error!("{}", error);
+            return (BTreeMap::new(), 1);
+        }
+    };
+
+    if subopts.input.len() == 1 && subopts.input[0] == Path::new("-") {
+        if subopts.verify_first {
+            warn!("--verify-first only applies to files, stdin is recompressed as it is");
+        }
+        return (BTreeMap::new(), recompress_stdin(subopts, &options));
+    }
+
+    let summaries = Summaries::default();
+
+    if subopts.format == Format::Csv {
+        println!("{}", RECOMPRESS_CSV_HEADER);
+    }
+
+    let safe = subopts.safe;
+    let only_junk = subopts.only_junk;
+    let verify_digest = subopts.verify_digest;
+    let verify_first = subopts.verify_first;
+    let mcc_dir = subopts.mcc.mcc_dir.clone();
+    let format = subopts.format;
+    let preserve_mtime = subopts.preserve_mtime;
+    let backup = subopts.backup;
+    let summary = summaries.clone();
+    let process = move |(file, dest): (DirEntry, Option<PathBuf>)| {
+        if dest.is_none() && region_is_empty(file.path()) {
+            debug!("Skipping {}, it has no chunks", file.path().display());
+            report_recompress(file.path(), &region::RecompressStats::default(), format);
+            add_to_summary(&summary, file.path(), 0, 0);
+            return false;
+        }
+
+        let _lock = match lock_region(file.path(), backup.force) {
+            Ok(lock) => lock,
+            Err(error) => {
+                warn!("Skipping {}: {}", file.path().display(), error);
+                return false;
+            }
+        };
+
+        if verify_first {
+            if let Some(reason) = verify_first_failure(file.path()) {
+                warn!("Skipping {}: {}", file.path().display(), reason);
+                return false;
+            }
+        }
+
+        let backup_path = match backup.create(file.path()) {
+            Ok(path) => path,
+            Err(error) => {
+                error!(
+                    "Error while backing up {}: {}",
+                    file.path().display(),
+                    error
+                );
+                return true;
+            }
+        };
+
+        let res = || -> Result<region::RecompressStats, region::Error> {
+            let written = dest.as_deref().unwrap_or_else(|| file.path());
+            let digest = match verify_digest {
+                true => Some(file_digest(file.path())?),
+                false => None,
+            };
+            let stats = preserving_mtime(file.path(), written, preserve_mtime, || {
+                if let Some(dest) = &dest {
+                    return recompress_to(file.path(), dest, &options, mcc_dir.as_deref());
+                }
+
+                if safe {
+                    return compact_atomic(file.path(), Some(&options), mcc_dir.as_deref());
+                }
+
+                let f = OpenOptions::new()
+                    .write(true)
+                    .read(true)
+                    .open(file.path())?;
+                let mut region = region::RegionFile::new(f)?;
+
+                region.recompress_region_filtered(
+                    &options,
+                    |region, x, z| Ok(!only_junk || has_junk(region, x, z)),
+                    |x, z, before, after| {
+                        debug!(
+                            "{}: chunk {} {}: {} -> {} bytes",
+                            file.path().display(),
+                            x,
+                            z,
+                            before,
+                            after
+                        );
+                    },
+                )
+            })?;
+
+            if let Some(before) = digest {
+                check_digest(before, file_digest(written)?)?;
+            }
+            Ok(stats)
+        };
+
+        match res() {
+            Ok(stats) => {
+                report_recompress(file.path(), &stats, format);
+                add_to_summary(
+                    &summary,
+                    file.path(),
+                    stats.chunks_recompressed,
+                    stats.bytes_before as i64 - stats.bytes_after as i64,
+                );
+                false
+            }
+            Err(region::Error::RegionGrew {
+                bytes_before,
+                bytes_after,
+            }) => {
+                info!(
+                    "Skipped {}, would have grown from {} to {} bytes",
+                    file.path().display(),
+                    bytes_before,
+                    bytes_after
+                );
+                false
+            }
+            Err(error) => {
+                error!(
+                    "Error while processing {}: {:?}",
+                    file.path().display(),
+                    error
+                );
+
+                // the file may have been partially rewritten, so put the original back
+                if let Some(backup_path) = backup_path {
+                    if let Err(error) = fs::copy(&backup_path, file.path()) {
+                        error!(
+                            "Error while restoring {} from {}: {}",
+                            file.path().display(),
+                            backup_path.display(),
+                            error
+                        );
+                    }
+                }
+                true
+            }
+        }
+    };
+
+    let mut failures = 0;
+    let inputs = unlocked_inputs(&subopts.input, subopts.backup.force);
+    let files = with_destinations(
+        selected_files(&inputs, &subopts.select, &mut failures),
+        subopts.output_dir.as_deref(),
+    );
+    let progress = progress_bar(files.len());
+    run_jobs(
+        files,
+        thread_count(subopts.jobs, subopts.sequential),
+        process,
+        |failed| {
+            progress.inc(1);
+            failures += failed as usize;
+        },
+    );
+    progress.finish_and_clear();
+    (report_summaries(summaries), failures)
+}
+
+/// Recompresses a region file piped in through stdin and writes it to stdout.  As stdout carries
+/// the region itself, the statistics always end up in the log on stderr, whatever the format.
+fn recompress_stdin(subopts: &RecompressOpts, options: &region::RecompressOptions) -> usize {
+    let stdout = io::stdout();
+    let path = Path::new("-");
+    match recompress_stream(
+        io::stdin().lock(),
+        stdout.lock(),
+        options,
+        subopts.only_junk,
+        subopts.verify_digest,
+    ) {
+        Ok(stats) if stats.failed_chunks.is_empty() => {
+            info!("{}", format_recompress(path, &stats, subopts.format))
+        }
+        Ok(stats) => warn!("{}", format_recompress(path, &stats, subopts.format)),
+        Err(region::Error::RegionGrew {
+            bytes_before,
+            bytes_after,
+        }) => info!(
+            "Skipped -, would have grown from {} to {} bytes",
+            bytes_before, bytes_after
+        ),
+        Err(error) => {
+            error!("Error while processing stdin: {:?}", error);
+            return 1;
+        }
+    }
+    0
+}
+
+/// A report in json output, with the path of the file it belongs to added in front of its fields
+#[derive(serde::Serialize)]
+struct JsonReport<'a, T> {
+    path: Cow<'a, str>,
+    #[serde(flatten)]
+    report: &'a T,
+}
+
+/// Formats the report of a file as a single line of json
+fn to_json<T: serde::Serialize>(path: &Path, report: &T) -> String {
+    let report = JsonReport {
+        path: path.to_string_lossy(),
+        report,
+    };
+    // the reports are plain structs, which always serialize to json
+    serde_json::to_string(&report).expect("reports serialize to json")
+}
+
+/// Quotes a field for use in csv output, if needed
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn compression_name(compression_type: u8) -> &'static str {
+    match region::CompressionFormat::try_from(compression_type) {
+        Ok(region::CompressionFormat::Gzip) => "gzip",
+        Ok(region::CompressionFormat::Zlib) => "zlib",
+        Ok(region::CompressionFormat::Uncompressed) => "uncompressed",
+        Ok(region::CompressionFormat::Zstd) => "zstd",
+        Err(_) => "unknown",
+    }
+}
+
+fn format_info(path: &Path, report: &region::InfoReport, format: Format) -> String {
+    let optional = |v: Option<u32>| v.map_or("null".to_string(), |v| v.to_string());
+
+    match format {
+        Format::Text => {
+            let mut out = format!("{}\n", path.display());
+            out += &format!("  chunks: {}\n", report.chunks);
+            out += &format!(
+                "  sectors: {} total, {} used, {} free\n",
+                report.total_sectors,
+                report.used_sectors,
+                report.total_sectors.saturating_sub(report.used_sectors)
+            );
+            out += &format!(
+                "  data: {} bytes in {} allocated bytes\n",
+                report.data_bytes, report.allocated_bytes
+            );
+            for (compression_type, count) in &report.compression_types {
+                out += &format!(
+                    "  compression type {} ({}): {} chunks\n",
+                    compression_type,
+                    compression_name(*compression_type),
+                    count
+                );
+            }
+            #[cfg(feature = "nbt")]
+            {
+                for (data_version, count) in &report.data_versions {
+                    out += &format!("  data version {}: {} chunks\n", data_version, count);
+                }
+                if report.chunks_without_data_version > 0 {
+                    out += &format!(
+                        "  no data version: {} chunks\n",
+                        report.chunks_without_data_version
+                    );
+                }
+            }
+            out += &format!(
+                "  timestamps: oldest {}, newest {}",
+                optional(report.oldest_timestamp),
+                optional(report.newest_timestamp)
+            );
+            out
+        }
+        Format::Json => to_json(path, report),
+        // a summary doesn't fit in a table, csv lists the chunks instead
+        Format::Csv => unreachable!("info --format csv goes through info_chunks_handle"),
+    }
+}
+
+const CHUNKS_CSV_HEADER: &str =
+    "path,x,z,offset,sector_count,data_length,junk_bytes,timestamp,compression_type";
+
+fn format_chunk_entry(path: &Path, entry: &region::ChunkEntry, format: Format) -> String {
+    match format {
+        Format::Text => format!(
+            "{} chunk {} {}: offset {}, {} sectors, {} bytes of data, {} junk bytes, modified at {}, compression type {}",
+            path.display(),
+            entry.x,
+            entry.z,
+            entry.offset,
+            entry.sectors,
+            entry.data_length,
+            entry.junk_bytes,
+            entry.timestamp,
+            entry.compression_type
+        ),
+        Format::Json => to_json(path, entry),
+        Format::Csv => format!(
+            "{},{},{},{},{},{},{},{},{}",
+            csv_field(&path.to_string_lossy()),
+            entry.x,
+            entry.z,
+            entry.offset,
+            entry.sectors,
+            entry.data_length,
+            entry.junk_bytes,
+            entry.timestamp,
+            entry.compression_type
+        ),
+    }
+}
+
+const RECOMPRESS_CSV_HEADER: &str =
+    "path,bytes_before,bytes_after,chunks_recompressed,chunks_skipped,chunks_failed";
+
+/// Lists chunk coordinates as `(x, z) (x, z)`
+fn format_chunks(chunks: &[(u8, u8)]) -> String {
+    let chunks: Vec<String> = chunks
+        .iter()
+        .map(|(x, z)| format!("({}, {})", x, z))
+        .collect();
+    chunks.join(" ")
+}
+
+/// Prints the statistics of a recompressed file, files that had failed chunks are reported even
+/// when quiet.  The json and csv formats are always printed to stdout, as they're meant for other
+/// tools to read.
+fn report_recompress(path: &Path, stats: &region::RecompressStats, format: Format) {
+    let failed = !stats.failed_chunks.is_empty();
+    match format {
+        Format::Text if failed => warn!("{}", format_recompress(path, stats, format)),
+        Format::Text => info!("{}", format_recompress(path, stats, format)),
+        _ if failed || log::log_enabled!(log::Level::Info) => {
+            println!("{}", format_recompress(path, stats, format))
+        }
+        _ => {}
+    }
+}
+
+fn format_recompress(path: &Path, stats: &region::RecompressStats, format: Format) -> String {
+    match format {
+        Format::Text => {
+            let mut out = format!(
+                "Processed {}: {} -> {} bytes, {} chunks recompressed, {} skipped, {} failed",
+                path.display(),
+                stats.bytes_before,
+                stats.bytes_after,
+                stats.chunks_recompressed,
+                stats.chunks_skipped,
+                stats.failed_chunks.len()
+            );
+            if !stats.failed_chunks.is_empty() {
+                out.push_str(&format!(": {}", format_chunks(&stats.failed_chunks)));
+            }
+            out
+        }
+        Format::Json => to_json(path, stats),
+        Format::Csv => format!(
+            "{},{},{},{},{},{}",
+            csv_field(&path.to_string_lossy()),
+            stats.bytes_before,
+            stats.bytes_after,
+            stats.chunks_recompressed,
+            stats.chunks_skipped,
+            stats.failed_chunks.len()
+        ),
+    }
+}
+
+/// Prints the header entry of every chunk in the files, rather than a summary per file
+fn info_chunks_handle(subopts: &InfoOpts) -> usize {
+    if subopts.format == Format::Csv {
+        println!("{}", CHUNKS_CSV_HEADER);
+    }
+
+    let mut failures = 0;
+    for (_, file) in region_files(&subopts.input, &mut failures) {
+        let res = || -> Result<Vec<region::ChunkEntry>, region::Error> {
+            let mut region = region::RegionFile::new(File::open(file.path())?)?;
+            region.chunk_entries()
+        };
+
+        match res() {
+            Ok(entries) => {
+                for entry in &entries {
+                    println!("{}", format_chunk_entry(file.path(), entry, subopts.format));
+                }
+            }
+            Err(error) => {
+                error!(
+                    "Error while processing {}: {:?}",
+                    file.path().display(),
+                    error
+                );
+                failures += 1;
+            }
+        };
+    }
+    failures
+}
+
+/// Hexdumps the offset and timestamp tables of a region file, with every line annotated with the
+/// chunks its entries belong to
+fn format_header_dump(header: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, bytes) in header.chunks(16).enumerate() {
+        let (table, first) = match line * 4 {
+            idx if idx < 1024 => ("offsets", idx),
+            idx => ("timestamps", idx - 1024),
+        };
+        let entries: Vec<String> = bytes
+            .chunks(4)
+            .map(|entry| {
+                let hex: Vec<String> = entry.iter().map(|b| format!("{:02x}", b)).collect();
+                hex.join(" ")
+            })
+            .collect();
+        out.push_str(&format!(
+            "{:04x}  {:<10} {:>4}-{:<4} x={:>2}-{:<2} z={:<2}  {}\n",
+            line * 16,
+            table,
+            first,
+            first + 3,
+            first % 32,
+            first % 32 + 3,
+            first / 32,
+            entries.join("  ")
+        ));
+    }
+    out
+}
+
+/// Prints the raw header of every file, rather than a summary per file
+fn info_header_handle(subopts: &InfoOpts) -> usize {
+    let mut failures = 0;
+    for (_, file) in region_files(&subopts.input, &mut failures) {
+        let res = || -> Result<[u8; 8192], region::Error> {
+            let mut region = region::RegionFile::new(File::open(file.path())?)?;
+            Ok(region.header_bytes()?)
+        };
+
+        match res() {
+            Ok(header) => {
+                println!("{}:", file.path().display());
+                print!("{}", format_header_dump(&header));
+            }
+            Err(error) => {
+                error!(
+                    "Error while processing {}: {:?}",
+                    file.path().display(),
+                    error
+                );
+                failures += 1;
+            }
+        };
+    }
+    failures
+}
+
+/// Whether info lists every chunk rather than a summary per file, which is always the case for
+/// csv as the table has a row per chunk
+fn lists_chunks(subopts: &InfoOpts) -> bool {
+    subopts.chunks || subopts.format == Format::Csv
+}
+
+fn info_handle(subopts: &InfoOpts) -> usize {
+    if subopts.dump_header {
+        return info_header_handle(subopts);
+    }
+    if lists_chunks(subopts) {
+        return info_chunks_handle(subopts);
+    }
+
+    let mut failures = 0;
+    for (_, file) in region_files(&subopts.input, &mut failures) {
+        let res = || -> Result<region::InfoReport, region::Error> {
+            let f = File::open(file.path())?;
+            let mut region = region::RegionFile::new(f)?;
+
+            region.info()
+        };
+
+        match res() {
+            Ok(report) => {
+                println!("{}", format_info(file.path(), &report, subopts.format));
+            }
+            Err(error) => {
+                error!(
+                    "Error while processing {}: {:?}",
+                    file.path().display(),
+                    error
+                );
+                failures += 1;
+            }
+        };
+    }
+    failures
+}
+
+/// Counts the chunks containing junk and the total amount of junk bytes in a region file
+fn junk_report(path: &Path) -> Result<(usize, usize), region::Error> {
+    let mut region = region::RegionFile::new(File::open(path)?)?;
+
+    let junk = region.junk_map()?;
+    Ok((junk.len(), junk.iter().map(|(_, bytes)| bytes).sum()))
+}
+
+fn junk_handle(subopts: &JunkOpts) -> usize {
+    let mut failures = 0;
+    for (_, file) in region_files(&subopts.input, &mut failures) {
+        match junk_report(file.path()) {
+            Ok((chunks, bytes)) => {
+                println!(
+                    "{}: {} junk bytes in {} chunks",
+                    file.path().display(),
+                    bytes,
+                    chunks
+                );
+            }
+            Err(error) => {
+                error!(
+                    "Error while processing {}: {:?}",
+                    file.path().display(),
+                    error
+                );
+                failures += 1;
+            }
+        };
+    }
+    failures
+}
+
+/// The amount of chunks in a region file, the ones that failed and the bytes of its partial sector
+type VerifyReport = (usize, Vec<(u8, u8)>, usize);
+
+/// Counts the chunks in a region file and finds the ones that can't be read, or that share their
+/// sectors with another chunk.  Also tells how many bytes the file runs on past its last sector.
+/// Only the chunks in `range` are counted and checked.
+fn verify_report(
+    path: &Path,
+    mcc_dir: Option<&Path>,
+    range: region::ChunkRange,
+) -> Result<VerifyReport, region::Error> {
+    let mut region = region::RegionFile::new(File::open(path)?)?;
+    region.set_region_path_with_mcc_dir(path, mcc_dir);
+    let mut failed = region.verify_in(range);
+    for (first, second) in region.duplicate_offsets() {
+        failed.push(first);
+        failed.push(second);
+    }
+    failed.retain(|(x, z)| range.contains(*x, *z));
+    failed.sort_by_key(|(x, z)| (*z, *x));
+    failed.dedup();
+    Ok((
+        region
+            .chunks()
+            .filter(|(x, z)| range.contains(*x, *z))
+            .count(),
+        failed,
+        region.partial_sector_bytes()?,
+    ))
+}
+
+fn verify_handle(subopts: &VerifyOpts) -> usize {
+    let mut failures = 0;
+    for (_, file) in region_files(&subopts.input, &mut failures) {
+        let range = subopts.chunk_range.unwrap_or(region::ChunkRange::ALL);
+        let report = verify_report(file.path(), subopts.mcc.mcc_dir.as_deref(), range);
+        if let Ok((_, _, partial)) = report {
+            if partial != 0 {
+                warn!(
+                    "{}: ends in {} bytes of a partial sector, it may have been truncated",
+                    file.path().display(),
+                    partial
+                );
+            }
+        }
+        match report {
+            Ok((chunks, failed, _)) if failed.is_empty() => {
+                info!("{}: {} chunks ok", file.path().display(), chunks);
+            }
+            Ok((chunks, failed, _)) => {
+                warn!(
+                    "{}: {} chunks ok, {} failed: {}",
+                    file.path().display(),
+                    chunks - failed.len(),
+                    failed.len(),
+                    format_chunks(&failed)
+                );
+            }
+            Err(error) => {
+                error!(
+                    "Error while processing {}: {:?}",
+                    file.path().display(),
+                    error
+                );
+                failures += 1;
+            }
+        };
+    }
+    failures
+}
+
+/// Deletes the chunks of a region file that can't be recovered, returning their coordinates
+fn repair(path: &Path, mcc_dir: Option<&Path>) -> Result<Vec<(u8, u8)>, region::Error> {
+    let f = OpenOptions::new().write(true).read(true).open(path)?;
+    let mut region = region::RegionFile::new(f)?;
+    region.set_region_path_with_mcc_dir(path, mcc_dir);
+    region.repair()
+}
+
+fn repair_handle(subopts: &RepairOpts) -> usize {
+    let mut failures = 0;
+    let inputs = unlocked_inputs(&subopts.input, subopts.backup.force);
+    for (_, file) in region_files(&inputs, &mut failures) {
+        let _lock = match lock_region(file.path(), subopts.backup.force) {
+            Ok(lock) => lock,
+            Err(error) => {
+                warn!("Skipping {}: {}", file.path().display(), error);
+                continue;
+            }
+        };
+
+        let res = || -> Result<Vec<(u8, u8)>, region::Error> {
+            subopts.backup.create(file.path())?;
+            repair(file.path(), subopts.mcc.mcc_dir.as_deref())
+        };
+
+        match res() {
+            Ok(cleared) if cleared.is_empty() => {
+                info!("{}: nothing to repair", file.path().display());
+            }
+            Ok(cleared) => {
+                warn!(
+                    "{}: deleted {} unrecoverable chunks: {}",
+                    file.path().display(),
+                    cleared.len(),
+                    format_chunks(&cleared)
+                );
+            }
+            Err(error) => {
+                error!(
+                    "Error while processing {}: {:?}",
+                    file.path().display(),
+                    error
+                );
+                failures += 1;
+            }
+        };
+    }
+    failures
+}
+
+/// Describes how much space defragmenting a region file freed up
+fn format_defrag(path: &Path, stats: &region::RecompressStats) -> String {
+    if stats.sectors_after >= stats.sectors_before {
+        return format!(
+            "{}: nothing to reclaim, {} sectors",
+            path.display(),
+            stats.sectors_before
+        );
+    }
+    format!(
+        "{}: reclaimed {} KiB, {} -> {} sectors",
+        path.display(),
+        (stats.sectors_before - stats.sectors_after) * region::SECTOR_SIZE / 1024,
+        stats.sectors_before,
+        stats.sectors_after
+    )
+}
+
+fn defrag_handle(subopts: &DefragOpts) -> usize {
+    let mut failures = 0;
+    let inputs = unlocked_inputs(&subopts.input, subopts.backup.force);
+    for (_, file) in region_files(&inputs, &mut failures) {
+        let _lock = match lock_region(file.path(), subopts.backup.force) {
+            Ok(lock) => lock,
+            Err(error) => {
+                warn!("Skipping {}: {}", file.path().display(), error);
+                continue;
+            }
+        };
+
+        let res = || -> Result<region::RecompressStats, region::Error> {
+            subopts.backup.create(file.path())?;
+            compact_atomic(file.path(), None, subopts.mcc.mcc_dir.as_deref())
+        };
+
+        match res() {
+            Ok(stats) => info!("{}", format_defrag(file.path(), &stats)),
+            Err(error) => {
+                error!(
+                    "Error while processing {}: {:?}",
+                    file.path().display(),
+                    error
+                );
+                failures += 1;
+            }
+        };
+    }
+    failures
+}
+
+/// Deletes all the chunks players have spent fewer than `ticks` in, returning how many there were
+#[cfg(feature = "nbt")]
+fn prune(path: &Path, ticks: i64) -> Result<usize, region::Error> {
+    let f = OpenOptions::new().write(true).read(true).open(path)?;
+    let mut region = region::RegionFile::new(f)?;
+
+    let chunks = region.chunks_below_inhabited(ticks)?;
+    for (x, z) in &chunks {
+        region.delete_chunk(*x, *z)?;
+    }
+    Ok(chunks.len())
+}
+
+#[cfg(feature = "nbt")]
+fn prune_handle(subopts: &PruneOpts) -> usize {
+    let mut failures = 0;
+    let inputs = unlocked_inputs(&subopts.input, subopts.backup.force);
+    for (_, file) in region_files(&inputs, &mut failures) {
+        let _lock = match lock_region(file.path(), subopts.backup.force) {
+            Ok(lock) => lock,
+            Err(error) => {
+                warn!("Skipping {}: {}", file.path().display(), error);
+                continue;
+            }
+        };
+
+        let res = || -> Result<usize, region::Error> {
+            subopts.backup.create(file.path())?;
+            prune(file.path(), subopts.inhabited_below)
+        };
+
+        match res() {
+            Ok(chunks) => {
+                info!("Pruned {} chunks from {}", chunks, file.path().display());
+            }
+            Err(error) => {
+                error!(
+                    "Error while processing {}: {:?}",
+                    file.path().display(),
+                    error
+                );
+                failures += 1;
+            }
+        };
+    }
+    failures
+}
+
+/// A chunk along with the amount of block entities and entities in it
+#[cfg(feature = "nbt")]
+struct EntityCount {
+    path: PathBuf,
+    chunk: (u8, u8),
+    block_entities: usize,
+    entities: usize,
+}
+
+/// Counts the block entities and entities of every chunk in a region file
+#[cfg(feature = "nbt")]
+fn entity_counts(path: &Path) -> Result<Vec<EntityCount>, region::Error> {
+    let mut region = region::RegionFile::new(File::open(path)?)?;
+    let chunks: Vec<(u8, u8)> = region.chunks().collect();
+    let mut out = Vec::with_capacity(chunks.len());
+    for (x, z) in chunks {
+        let (block_entities, entities) = region.chunk_entity_counts(x, z)?;
+        out.push(EntityCount {
+            path: path.to_path_buf(),
+            chunk: (x, z),
+            block_entities,
+            entities,
+        });
+    }
+    Ok(out)
+}
+
+/// Finds the `top` chunks with the most block entities and entities combined over all the region
+/// files in `inputs`, heaviest first
+#[cfg(feature = "nbt")]
+fn heaviest_chunks(inputs: &[PathBuf], top: usize, failures: &mut usize) -> Vec<EntityCount> {
+    let mut out = Vec::new();
+    for (_, file) in region_files(inputs, failures) {
+        match entity_counts(file.path()) {
+            Ok(counts) => out.extend(counts),
+            Err(error) => {
+                error!(
+                    "Error while processing {}: {:?}",
+                    file.path().display(),
+                    error
+                );
+                *failures += 1;
+            }
+        }
+    }
+
+    // the files and their chunks come in order, so the sort being stable keeps the output the same
+    // on every run
+    out.sort_by_key(|count| std::cmp::Reverse(count.block_entities + count.entities));
+    out.truncate(top);
+    out
+}
+
+#[cfg(feature = "nbt")]
+fn entities_handle(subopts: &EntitiesOpts) -> usize {
+    let mut failures = 0;
+    for count in heaviest_chunks(&subopts.input, subopts.top, &mut failures) {
+        println!(
+            "{}: chunk {} {}: {} block entities, {} entities",
+            count.path.display(),
+            count.chunk.0,
+            count.chunk.1,
+            count.block_entities,
+            count.entities
+        );
+    }
+    failures
+}
+
+/// Writes the decompressed nbt of a single chunk to `out`
+fn extract(
+    input: &Path,
+    x: u8,
+    z: u8,
+    out: &Path,
+    raw: bool,
+    mcc_dir: Option<&Path>,
+) -> Result<(), region::Error> {
+    let mut region = region::RegionFile::new(File::open(input)?)?;
+    region.set_region_path_with_mcc_dir(input, mcc_dir);
+    let data = region.load_chunk(x, z)?;
+
+    let mut f = BufWriter::new(File::create(out)?);
+    if raw {
+        f.write_all(&data)?;
+    } else {
+        let mut encoder = flate2::write::GzEncoder::new(&mut f, Compression::default());
+        encoder.write_all(&data)?;
+        encoder.finish()?;
+    }
+    f.flush()?;
+
+    Ok(())
+}
+
+/// Writes the nbt in `input` to a chunk of a region file
+fn import(
+    region: &Path,
+    x: u8,
+    z: u8,
+    input: &Path,
+    timestamp: u32,
+    mcc_dir: Option<&Path>,
+) -> Result<(), region::Error> {
+    let mut data = fs::read(input)?;
+
+    // standalone .nbt files are usually gzip compressed
+    if data.starts_with(&[0x1f, 0x8b]) {
+        let mut decoded = Vec::new();
+        io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&data[..]), &mut decoded)?;
+        data = decoded;
+    }
+
+    let f = OpenOptions::new().write(true).read(true).open(region)?;
+    let path = region;
+    let mut region = region::RegionFile::new(f)?;
+    region.set_region_path_with_mcc_dir(path, mcc_dir);
+    region.write_chunk(x, z, &data, timestamp)
+}
+
+fn import_handle(subopts: &ImportOpts) -> usize {
+    let (x, z) = (subopts.chunk[0], subopts.chunk[1]);
+    let timestamp = subopts.timestamp.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |v| v.as_secs() as u32)
+    });
+
+    let mcc_dir = subopts.mcc.mcc_dir.as_deref();
+    match import(&subopts.region, x, z, &subopts.input, timestamp, mcc_dir) {
+        Ok(()) => {
+            info!(
+                "Imported {} as chunk {} {} of {}",
+                subopts.input.display(),
+                x,
+                z,
+                subopts.region.display()
+            );
+        }
+        Err(error) => {
+            error!(
+                "Error while processing {}: {:?}",
+                subopts.region.display(),
+                error
+            );
+            return 1;
+        }
+    };
+    0
+}
+
+fn extract_handle(subopts: &ExtractOpts) -> usize {
+    let (x, z) = (subopts.chunk[0], subopts.chunk[1]);
+    let mcc_dir = subopts.mcc.mcc_dir.as_deref();
+    match extract(&subopts.input, x, z, &subopts.out, subopts.raw, mcc_dir) {
+        Ok(()) => {
+            info!(
+                "Extracted chunk {} {} from {} to {}",
+                x,
+                z,
+                subopts.input.display(),
+                subopts.out.display()
+            );
+        }
+        Err(error) => {
+            error!(
+                "Error while processing {}: {:?}",
+                subopts.input.display(),
+                error
+            );
+            return 1;
+        }
+    };
+    0
+}
+
+/// Runs a subcommand, returning how many files or parts of the inputs couldn't be processed
+fn run(opts: Opts) -> usize {
+    match opts.subcmd {
+        SubCommand::Cleanup(subopts) => cleanup_handle(&subopts).1,
+        SubCommand::Recompress(subopts) => recompress_handle(&subopts).1,
+        SubCommand::Info(subopts) => info_handle(&subopts),
+        SubCommand::Junk(subopts) => junk_handle(&subopts),
+        SubCommand::Verify(subopts) => verify_handle(&subopts),
+        SubCommand::Repair(subopts) => repair_handle(&subopts),
+        SubCommand::Defrag(subopts) => defrag_handle(&subopts),
+        SubCommand::Extract(subopts) => extract_handle(&subopts),
+        SubCommand::Import(subopts) => import_handle(&subopts),
+        #[cfg(feature = "nbt")]
+        SubCommand::Prune(subopts) => prune_handle(&subopts),
+        #[cfg(feature = "nbt")]
+        SubCommand::Entities(subopts) => entities_handle(&subopts),
+    }
+}
+
+/// Sets up the logger everything but the reports is printed with, at the level the flags ask for.
+/// `RUST_LOG` can still be used to override it.
+fn logger(quiet: bool, verbose: bool) -> env_logger::Logger {
+    let level = if quiet {
+        log::LevelFilter::Warn
+    } else if verbose {
+        log::LevelFilter::Debug
+    } else {
+        log::LevelFilter::Info
+    };
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .build()
+}
+
+fn main() -> ExitCode {
+    let opts = Opts::parse();
+
+    let logger = logger(opts.quiet, opts.verbose);
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(logger)).expect("the logger is only set up once");
+
+    // anything that was skipped because of an error makes the whole run fail, so scripts notice
+    match run(opts) {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_region_files_broken_symlink() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::copy("tests/data/r.0.0.mca", dir.path().join("r.0.0.mca")).unwrap();
+    std::os::unix::fs::symlink(dir.path().join("missing"), dir.path().join("r.1.0.mca")).unwrap();
+
+    let files: Vec<PathBuf> = region_files(&[dir.path().to_path_buf()], &mut 0)
+        .into_iter()
+        .map(|(_, e)| e.into_path())
+        .collect();
+    assert_eq!(files, vec![dir.path().join("r.0.0.mca")]);
+}
+
+/// Collects the warnings logged on the current thread while running `f`
+#[cfg(test)]
+fn capture_warnings<F: FnOnce()>(f: F) -> Vec<String> {
+    use std::cell::RefCell;
+
+    thread_local! {
+        static WARNINGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    struct Capture;
+
+    impl log::Log for Capture {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                WARNINGS.with(|warnings| warnings.borrow_mut().push(record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture;
+    if log::set_logger(&CAPTURE).is_ok() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+
+    WARNINGS.with(|warnings| warnings.borrow_mut().clear());
+    f();
+    WARNINGS.with(|warnings| warnings.take())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_region_files_walk_errors() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::copy("tests/data/r.0.0.mca", dir.path().join("r.0.0.mca")).unwrap();
+    let missing = dir.path().join("missing");
+    let locked = dir.path().join("locked");
+    std::fs::create_dir(&locked).unwrap();
+    std::fs::copy("tests/data/r.0.0.mca", locked.join("r.1.0.mca")).unwrap();
+    std::fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+    // root can read the directory regardless of its permissions
+    let unreadable = fs::read_dir(&locked).is_err();
+
+    let inputs = [dir.path().to_path_buf(), missing.clone()];
+    let mut files = Vec::new();
+    let mut failures = 0;
+    let warnings = capture_warnings(|| {
+        files = region_files(&inputs, &mut failures)
+            .into_iter()
+            .map(|(_, e)| e.into_path())
+            .collect();
+    });
+    let verify_failures = run(Opts::parse_from([
+        Path::new("mccompress"),
+        Path::new("verify"),
+        dir.path(),
+        &missing,
+    ]));
+    std::fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+    // the walk errors are what makes the exit status a failure
+    assert_eq!(failures, if unreadable { 2 } else { 1 });
+    assert_eq!(verify_failures, failures);
+
+    assert!(files.contains(&dir.path().join("r.0.0.mca")));
+    assert!(
+        warnings
+            .iter()
+            .any(|w| w.starts_with(&format!("Skipping {}: ", missing.display()))),
+        "{:?}",
+        warnings
+    );
+    if unreadable {
+        assert_eq!(files, vec![dir.path().join("r.0.0.mca")]);
+        assert!(
+            warnings
+                .iter()
+                .any(|w| w.starts_with(&format!("Skipping {}: ", locked.display()))),
+            "{:?}",
+            warnings
+        );
+    }
+}
+
+#[test]
+fn test_region_files_vanished() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::copy("tests/data/r.0.0.mca", dir.path().join("r.0.0.mca")).unwrap();
+
+    let entries: Vec<DirEntry> = WalkDir::new(dir.path())
+        .into_iter()
+        .filter_map(|v| v.ok())
+        .filter(|e| e.file_type().is_file())
+        .collect();
+    assert_eq!(entries.len(), 1);
+
+    std::fs::remove_file(entries[0].path()).unwrap();
+    let mut failures = 0;
+    assert!(!is_nonempty_file(&entries[0], &mut failures));
+    assert_eq!(failures, 1);
+}
+
+#[test]
+fn test_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    let original = std::fs::read(&path).unwrap();
+
+    run(Opts::parse_from([
+        "mccompress",
+        "recompress",
+        "--backup",
+        "--jobs",
+        "1",
+        dir.path().to_str().unwrap(),
+    ]));
+
+    let backup = std::fs::read(dir.path().join("r.0.0.mca.bak")).unwrap();
+    assert_eq!(backup, original);
+    assert_ne!(std::fs::read(&path).unwrap(), original);
+
+    // a second run must not clobber the existing backup without --force
+    let opts = BackupOpts {
+        backup: true,
+        force: false,
+    };
+    assert!(opts.create(&path).is_err());
+    assert_eq!(
+        std::fs::read(dir.path().join("r.0.0.mca.bak")).unwrap(),
+        original
+    );
+
+    let opts = BackupOpts {
+        backup: true,
+        force: true,
+    };
+    assert!(opts.create(&path).unwrap().is_some());
+    assert_eq!(
+        std::fs::read(dir.path().join("r.0.0.mca.bak")).unwrap(),
+        std::fs::read(&path).unwrap()
+    );
+}
+
+#[test]
+fn test_compact_atomic() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    let original = std::fs::read(&path).unwrap();
+
+    compact_atomic(
+        &path,
+        Some(&region::RecompressOptions::new(Compression::fast())),
+        None,
+    )
+    .unwrap();
+    let recompressed = std::fs::read(&path).unwrap();
+    assert_ne!(recompressed, original);
+    assert!(region::RegionFile::new(io::Cursor::new(recompressed)).is_ok());
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+    // mark the chunk at (14, 10) as using an unknown compression type, so the recompress fails
+    // after already having written part of the temporary file
+    let mut broken = original.clone();
+    let idx = (14 + 10 * 32) * 4;
+    let sector = u32::from_be_bytes([broken[idx], broken[idx + 1], broken[idx + 2], 0]) >> 8;
+    broken[sector as usize * 4096 + 4] = 42;
+    std::fs::write(&path, &broken).unwrap();
+
+    assert!(compact_atomic(
+        &path,
+        Some(&region::RecompressOptions::new(Compression::fast())),
+        None
+    )
+    .is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), broken);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_verify_digest() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    let before = file_digest(&path).unwrap();
+
+    run(Opts::parse_from([
+        "mccompress",
+        "recompress",
+        "--level",
+        "best",
+        "--verify-digest",
+        path.to_str().unwrap(),
+    ]));
+    assert_ne!(
+        std::fs::read(&path).unwrap(),
+        std::fs::read("tests/data/r.0.0.mca").unwrap()
+    );
+    assert_eq!(file_digest(&path).unwrap(), before);
+
+    assert!(check_digest(before, before).is_ok());
+    assert!(matches!(
+        check_digest(before, [0; 32]),
+        Err(region::Error::Io(error)) if error.kind() == io::ErrorKind::InvalidData
+    ));
+}
+
+#[test]
+fn test_to_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    let mut region =
+        region::RegionFile::from_bytes(std::fs::read("tests/data/r.0.0.mca").unwrap()).unwrap();
+    region
+        .convert_format(region::CompressionFormat::Gzip, Compression::fast())
+        .unwrap();
+    region.write_chunk(31, 31, b"zlib", 1).unwrap();
+    std::fs::write(&path, region.into_inner().into_inner()).unwrap();
+
+    run(Opts::parse_from([
+        "mccompress",
+        "recompress",
+        "--level",
+        "best",
+        "--to-format",
+        "zlib",
+        path.to_str().unwrap(),
+    ]));
+    let mut region = region::RegionFile::new(File::open(&path).unwrap()).unwrap();
+    let chunks: Vec<(u8, u8)> = region.chunks().collect();
+    assert_eq!(chunks.len(), 382);
+    for (x, z) in chunks {
+        assert_eq!(
+            region.get_compression_type(x, z).unwrap(),
+            region::CompressionFormat::Zlib
+        );
+    }
+    assert_eq!(region.load_chunk(31, 31).unwrap(), b"zlib");
+}
+
+#[test]
+fn test_to_format_with_level_none() {
+    let options = |format: &str| {
+        let opts = Opts::try_parse_from([
+            "mccompress",
+            "recompress",
+            "--level",
+            "none",
+            "--to-format",
+            format,
+            "world",
+        ])
+        .unwrap();
+        match opts.subcmd {
+            SubCommand::Recompress(subopts) => recompress_options(&subopts),
+            _ => unreachable!(),
+        }
+    };
+
+    assert_eq!(
+        options("zlib").unwrap_err(),
+        "--level none stores the chunks uncompressed, it can't be combined with --to-format zlib"
+    );
+    assert!(options("gzip").is_err());
+    assert_eq!(
+        options("uncompressed").unwrap().target_format,
+        Some(region::CompressionFormat::Uncompressed)
+    );
+
+    // nothing is touched when the options are refused
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    let failures = run(Opts::parse_from([
+        Path::new("mccompress"),
+        Path::new("recompress"),
+        Path::new("--level"),
+        Path::new("none"),
+        Path::new("--to-format"),
+        Path::new("zlib"),
+        &path,
+    ]));
+    assert_eq!(failures, 1);
+    assert_eq!(
+        std::fs::read(&path).unwrap(),
+        std::fs::read("tests/data/r.0.0.mca").unwrap()
+    );
+}
+
+#[test]
+fn test_chunk_range() {
+    assert_eq!(
+        parse_chunk_range("0,0,7,7"),
+        Ok(region::ChunkRange::new(0, 0, 7, 7).unwrap())
+    );
+    assert_eq!(
+        parse_chunk_range("7, 0, 0, 7"),
+        Ok(region::ChunkRange::new(0, 0, 7, 7).unwrap())
+    );
+    for invalid in &["0,0,32,7", "0,0,7", "0,0,7,7,7", "a,0,7,7", "-1,0,7,7", ""] {
+        assert!(parse_chunk_range(invalid).is_err(), "{}", invalid);
+    }
+    assert!(
+        Opts::try_parse_from(["mccompress", "verify", "--chunk-range", "0,0,40,0", "."]).is_err()
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    run(Opts::parse_from([
+        "mccompress",
+        "recompress",
+        "--level",
+        "best",
+        "--chunk-range",
+        "0,0,7,7",
+        path.to_str().unwrap(),
+    ]));
+
+    // only the chunks in the rectangle were rewritten
+    let before = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let after = std::fs::read(&path).unwrap();
+    let region = region::RegionFile::new(File::open(&path).unwrap()).unwrap();
+    let range = region::ChunkRange::new(0, 0, 7, 7).unwrap();
+    let mut changed = 0;
+    for (x, z) in region.chunks() {
+        let (sector, count) = region.chunk_sector_range(x, z).unwrap();
+        let sectors = sector as usize * 4096..(sector as usize + count as usize) * 4096;
+        if before[sectors.clone()] != after[sectors] {
+            assert!(range.contains(x, z), "{} {}", x, z);
+            changed += 1;
+        }
+    }
+    assert!(changed > 0);
+
+    let (chunks, failed, _) = verify_report(&path, None, range).unwrap();
+    assert_eq!(
+        chunks,
+        region.chunks().filter(|(x, z)| x < &8 && z < &8).count()
+    );
+    assert!(failed.is_empty());
+}
+
+#[test]
+fn test_recompress_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+
+    let options = region::RecompressOptions::new(Compression::best());
+    let input = File::open("tests/data/r.0.0.mca").unwrap();
+    let mut output = Vec::new();
+    let piped = recompress_stream(input, &mut output, &options, false, false).unwrap();
+
+    let f = OpenOptions::new()
+        .write(true)
+        .read(true)
+        .open(&path)
+        .unwrap();
+    let in_place = region::RegionFile::new(f)
+        .unwrap()
+        .recompress_region(&options)
+        .unwrap();
+    assert_eq!(piped.bytes_after, in_place.bytes_after);
+    assert_eq!(output, std::fs::read(&path).unwrap());
+
+    // stdin that isn't a region at all is refused
+    let mut output = Vec::new();
+    assert!(recompress_stream(&b"mca"[..], &mut output, &options, false, false).is_err());
+    assert!(output.is_empty());
+}
+
+#[test]
+fn test_dir_summaries() {
+    let world = tempfile::tempdir().unwrap();
+    let overworld = world.path().join("region");
+    let nether = world.path().join("DIM-1").join("region");
+    for (dir, name) in &[
+        (&overworld, "r.0.0.mca"),
+        (&overworld, "r.0.1.mca"),
+        (&nether, "r.0.0.mca"),
+    ] {
+        fs::create_dir_all(dir).unwrap();
+        fs::copy("tests/data/r.0.0.mca", dir.join(name)).unwrap();
+    }
+
+    let data = fs::read("tests/data/r.0.0.mca").unwrap();
+    let cleaned = region::RegionFile::from_bytes(data)
+        .unwrap()
+        .clean_junk()
+        .unwrap() as i64;
+
+    let opts = Opts::parse_from(["mccompress", "cleanup", world.path().to_str().unwrap()]);
+    let summaries = match opts.subcmd {
+        SubCommand::Cleanup(subopts) => cleanup_handle(&subopts).0,
+        _ => unreachable!(),
+    };
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(
+        summaries[&overworld],
+        DirSummary {
+            files: 2,
+            chunks: 762,
+            bytes_saved: cleaned * 2,
+        }
+    );
+    assert_eq!(
+        summaries[&nether],
+        DirSummary {
+            files: 1,
+            chunks: 381,
+            bytes_saved: cleaned,
+        }
+    );
+}
+
+#[test]
+fn test_dimension_filter() {
+    let world = tempfile::tempdir().unwrap();
+    let dirs = [
+        world.path().join("region"),
+        world.path().join("entities"),
+        world.path().join("DIM-1").join("region"),
+        world.path().join("DIM1").join("region"),
+    ];
+    for dir in &dirs {
+        fs::create_dir_all(dir).unwrap();
+        fs::copy("tests/data/r.0.0.mca", dir.join("r.0.0.mca")).unwrap();
+    }
+
+    let opts = Opts::parse_from([
+        "mccompress",
+        "cleanup",
+        "--dimension",
+        "nether",
+        world.path().to_str().unwrap(),
+    ]);
+    let summaries = match opts.subcmd {
+        SubCommand::Cleanup(subopts) => cleanup_handle(&subopts).0,
+        _ => unreachable!(),
+    };
+    let processed: Vec<&PathBuf> = summaries.keys().collect();
+    assert_eq!(processed, vec![&dirs[2]]);
+
+    // only the nether file had its junk cleaned
+    let original = fs::read("tests/data/r.0.0.mca").unwrap();
+    for (i, dir) in dirs.iter().enumerate() {
+        let data = fs::read(dir.join("r.0.0.mca")).unwrap();
+        assert_eq!(data == original, i != 2);
+    }
+
+    assert!(Dimension::Overworld.contains(&dirs[1].join("r.0.0.mca")));
+    assert!(Dimension::End.contains(&dirs[3].join("r.0.0.mca")));
+    let custom = Path::new("world/dimensions/mymod/cave/region/r.0.0.mca");
+    assert_eq!(Dimension::of(custom), None);
+    assert!(Dimension::All.contains(custom));
+}
+
+#[test]
+fn test_exclude() {
+    let world = tempfile::tempdir().unwrap();
+    let names = [
+        "region/r.0.0.mca",
+        "region/r.0.1.mca",
+        "DIM1/region/r.0.0.mca",
+    ];
+    for name in &names {
+        let path = world.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::copy("tests/data/r.0.0.mca", path).unwrap();
+    }
+
+    let opts = Opts::parse_from([
+        "mccompress",
+        "cleanup",
+        "--exclude",
+        "*/region/r.0.0.mca",
+        world.path().to_str().unwrap(),
+    ]);
+    let summaries = match opts.subcmd {
+        SubCommand::Cleanup(subopts) => cleanup_handle(&subopts).0,
+        _ => unreachable!(),
+    };
+    assert_eq!(summaries[&world.path().join("region")].files, 1);
+    assert!(!summaries.contains_key(&world.path().join("DIM1").join("region")));
+
+    // both spawn regions were left alone
+    let original = fs::read("tests/data/r.0.0.mca").unwrap();
+    for (name, skipped) in names.iter().zip([true, false, true]) {
+        let data = fs::read(world.path().join(name)).unwrap();
+        assert_eq!(data == original, skipped);
+    }
+
+    // patterns can be given more than once, and bad ones are refused
+    let inputs = [world.path().to_path_buf()];
+    let select = SelectOpts {
+        dimension: Dimension::All,
+        include: Vec::new(),
+        exclude: vec![
+            parse_glob("**/r.0.1.mca").unwrap(),
+            parse_glob("*/DIM1/*").unwrap(),
+        ],
+    };
+    let files = selected_files(&inputs, &select, &mut 0);
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].1.path(), world.path().join(names[0]));
+    assert!(Opts::try_parse_from(["mccompress", "recompress", "--exclude", "r.[0", "w"]).is_err());
+}
+
+#[test]
+fn test_include() {
+    let world = tempfile::tempdir().unwrap();
+    let names = [
+        "region/r.-1.0.mca",
+        "region/r.-1.1.mca",
+        "region/r.0.0.mca",
+        "DIM-1/region/r.-1.0.mca",
+    ];
+    for name in &names {
+        let path = world.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::copy("tests/data/r.0.0.mca", path).unwrap();
+    }
+    let inputs = [world.path().to_path_buf()];
+    let selected = |args: &[&str]| {
+        let mut cmd = vec!["mccompress", "cleanup"];
+        cmd.extend_from_slice(args);
+        cmd.push(world.path().to_str().unwrap());
+        let subopts = match Opts::parse_from(cmd).subcmd {
+            SubCommand::Cleanup(subopts) => subopts,
+            _ => unreachable!(),
+        };
+        let files = selected_files(&inputs, &subopts.select, &mut 0);
+        files
+            .iter()
+            .map(|(_, file)| {
+                file.path()
+                    .strip_prefix(world.path())
+                    .unwrap()
+                    .to_path_buf()
+            })
+            .collect::<Vec<PathBuf>>()
+    };
+    let paths = |indices: &[usize]| {
+        indices
+            .iter()
+            .map(|i| PathBuf::from(names[*i]))
+            .collect::<Vec<PathBuf>>()
+    };
+
+    // a pattern without a directory matches the name of the file
+    assert_eq!(selected(&["--include", "r.-1.*.mca"]), paths(&[3, 0, 1]));
+    assert_eq!(selected(&["--include", "*/region/r.0.0.mca"]), paths(&[2]));
+    assert_eq!(
+        selected(&["--include", "r.0.0.mca", "--include", "r.-1.1.mca"]),
+        paths(&[1, 2])
+    );
+    assert_eq!(selected(&["--exclude", "r.-1.*.mca"]), paths(&[2]));
+
+    // exclude wins when both match, and the dimension still has to match as well
+    assert_eq!(
+        selected(&["--include", "r.-1.*.mca", "--exclude", "r.-1.1.mca"]),
+        paths(&[3, 0])
+    );
+    assert_eq!(
+        selected(&["--include", "r.-1.*.mca", "--dimension", "overworld"]),
+        paths(&[0, 1])
+    );
+
+    // only the included files are cleaned
+    let opts = Opts::parse_from([
+        "mccompress",
+        "cleanup",
+        "--include",
+        "r.-1.*.mca",
+        "--exclude",
+        "*/DIM-1/*",
+        world.path().to_str().unwrap(),
+    ]);
+    run(opts);
+    let original = fs::read("tests/data/r.0.0.mca").unwrap();
+    for (name, cleaned) in names.iter().zip([true, true, false, false]) {
+        let data = fs::read(world.path().join(name)).unwrap();
+        assert_eq!(data != original, cleaned);
+    }
+}
+
+#[test]
+fn test_progress_total() {
+    let world = tempfile::tempdir().unwrap();
+    let nether = world.path().join("DIM-1").join("region");
+    fs::create_dir_all(&nether).unwrap();
+    fs::create_dir_all(world.path().join("region")).unwrap();
+    for name in &[
+        "region/r.0.0.mca",
+        "region/r.0.1.mca",
+        "DIM-1/region/r.0.0.mca",
+    ] {
+        fs::copy("tests/data/r.0.0.mca", world.path().join(name)).unwrap();
+    }
+    fs::write(world.path().join("level.dat"), b"not a region").unwrap();
+    fs::write(world.path().join("region").join("r.1.1.mca"), b"").unwrap();
+
+    let inputs = [world.path().to_path_buf()];
+    let total = |dimension| {
+        let select = SelectOpts {
+            dimension,
+            include: Vec::new(),
+            exclude: Vec::new(),
+        };
+        progress_bar(selected_files(&inputs, &select, &mut 0).len()).length()
+    };
+    assert_eq!(total(Dimension::All), Some(3));
+    assert_eq!(total(Dimension::Overworld), Some(2));
+    assert_eq!(total(Dimension::Nether), Some(1));
+    assert_eq!(total(Dimension::End), Some(0));
+}
+
+#[test]
+fn test_output_dir() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    std::fs::create_dir(input.path().join("region")).unwrap();
+    let path = input.path().join("region").join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    let original = std::fs::read(&path).unwrap();
+
+    for subcmd in &["cleanup", "recompress"] {
+        let out = output.path().join(subcmd);
+        run(Opts::parse_from([
+            "mccompress",
+            subcmd,
+            "--jobs",
+            "1",
+            "--output-dir",
+            out.to_str().unwrap(),
+            input.path().to_str().unwrap(),
+        ]));
+
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+
+        let written = std::fs::read(out.join("region").join("r.0.0.mca")).unwrap();
+        assert_ne!(written, original);
+        assert!(region::RegionFile::new(io::Cursor::new(written)).is_ok());
+    }
+}
+
+#[test]
+fn test_recompress_to_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("r.0.0.mca");
+    let mut data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let offset = region::RegionFile::from_bytes(data.clone())
+        .unwrap()
+        .chunk_sector_range(14, 10)
+        .unwrap()
+        .0 as usize
+        * 4096;
+    data[offset..offset + 4].copy_from_slice(&0xffff_ffffu32.to_be_bytes());
+    std::fs::write(&src, &data).unwrap();
+
+    // a failure halfway through leaves whatever was at the destination alone
+    let options = region::RecompressOptions::new(Compression::best());
+    let dest = dir.path().join("out").join("r.0.0.mca");
+    assert!(recompress_to(&src, &dest, &options, None).is_err());
+    assert!(!dest.exists());
+    std::fs::write(&dest, b"previous").unwrap();
+    assert!(recompress_to(&src, &dest, &options, None).is_err());
+    assert_eq!(std::fs::read(&dest).unwrap(), b"previous");
+    assert_eq!(
+        std::fs::read_dir(dir.path().join("out")).unwrap().count(),
+        1
+    );
+}
+
+#[test]
+fn test_destination() {
+    assert_eq!(
+        destination(
+            Path::new("world"),
+            Path::new("world/DIM-1/region/r.0.0.mca"),
+            Path::new("out")
+        ),
+        Path::new("out/DIM-1/region/r.0.0.mca")
+    );
+    assert_eq!(
+        destination(
+            Path::new("world/region/r.0.0.mca"),
+            Path::new("world/region/r.0.0.mca"),
+            Path::new("out")
+        ),
+        Path::new("out/r.0.0.mca")
+    );
+}
+
+#[test]
+fn test_parse_level() {
+    let level = |level: &str| {
+        Opts::try_parse_from(["mccompress", "recompress", "--level", level, "world"]).map(|opts| {
+            match opts.subcmd {
+                SubCommand::Recompress(subopts) => subopts.level,
+                _ => unreachable!(),
+            }
+        })
+    };
+
+    assert!(level("10").is_err());
+    assert!(level("-1").is_err());
+    assert!(level("fastest").is_err());
+    assert_eq!(level("best").unwrap(), Level::Deflate(Compression::best()));
+    assert_eq!(level("fast").unwrap(), Level::Deflate(Compression::fast()));
+    assert_eq!(
+        level("default").unwrap(),
+        Level::Deflate(Compression::default())
+    );
+    assert_eq!(level("none").unwrap(), Level::Store);
+    assert_ne!(level("none").unwrap(), level("0").unwrap());
+    assert!(level("None").is_err());
+    assert_eq!(level("0").unwrap(), Level::Deflate(Compression::new(0)));
+    assert_eq!(level("9").unwrap(), Level::Deflate(Compression::new(9)));
+
+    // none stores chunks as compression type 3, which needs room to grow
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    run(Opts::parse_from([
+        "mccompress",
+        "recompress",
+        "--level",
+        "none",
+        "--safe",
+        "--quiet",
+        path.to_str().unwrap(),
+    ]));
+    let mut original =
+        region::RegionFile::new(File::open("tests/data/r.0.0.mca").unwrap()).unwrap();
+    let mut region = region::RegionFile::new(File::open(&path).unwrap()).unwrap();
+    let mut stored = 0;
+    for (x, z) in region.chunks().collect::<Vec<_>>() {
+        if region.get_compression_type(x, z).unwrap() == region::CompressionFormat::Uncompressed {
+            stored += 1;
+        }
+        assert_eq!(
+            region.load_chunk(x, z).unwrap(),
+            original.load_chunk(x, z).unwrap()
+        );
+    }
+    assert_eq!(stored, 381);
+}
+
+#[test]
+fn test_thread_count() {
+    let jobs = |args: &[&str]| match Opts::parse_from(args).subcmd {
+        SubCommand::Cleanup(subopts) => thread_count(subopts.jobs, subopts.sequential),
+        SubCommand::Recompress(subopts) => thread_count(subopts.jobs, subopts.sequential),
+        _ => unreachable!(),
+    };
+
+    let cpus = std::thread::available_parallelism().unwrap().get();
+    assert_eq!(jobs(&["mccompress", "cleanup", "world"]), cpus);
+    assert_eq!(jobs(&["mccompress", "recompress", "world"]), cpus);
+    assert_eq!(jobs(&["mccompress", "cleanup", "--jobs", "3", "world"]), 3);
+    assert_eq!(
+        jobs(&["mccompress", "cleanup", "--jobs", "0", "world"]),
+        cpus
+    );
+    assert_eq!(
+        jobs(&["mccompress", "recompress", "--threads", "0", "world"]),
+        cpus
+    );
+    assert_eq!(
+        jobs(&["mccompress", "recompress", "--sequential", "world"]),
+        1
+    );
+    assert!(
+        Opts::try_parse_from(["mccompress", "cleanup", "--sequential", "-j", "2", "w"]).is_err()
+    );
+}
+
+#[test]
+fn test_format_info() {
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let report = region::RegionFile::new(f).unwrap().info().unwrap();
+
+    let json = format_info(Path::new("r.0.0.mca"), &report, Format::Json);
+    assert_eq!(
+        json,
+        format!(
+            "{{\"path\":\"r.0.0.mca\",\"chunks\":381,\"total_sectors\":398,\"used_sectors\":398,\"data_bytes\":{},\"allocated_bytes\":{},\"compression_types\":{{\"2\":381}},\"oldest_timestamp\":1383443693,\"newest_timestamp\":1383443713{}}}",
+            report.data_bytes,
+            report.allocated_bytes,
+            if cfg!(feature = "nbt") {
+                ",\"data_versions\":{},\"chunks_without_data_version\":381"
+            } else {
+                ""
+            }
+        )
+    );
+
+    let text = format_info(Path::new("r.0.0.mca"), &report, Format::Text);
+    assert!(text.starts_with("r.0.0.mca\n  chunks: 381\n"));
+    assert!(text.contains("compression type 2 (zlib): 381 chunks"));
+
+    // paths with quotes in them are escaped
+    let json = format_info(Path::new("a\"b\\c.mca"), &report, Format::Json);
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["path"], "a\"b\\c.mca");
+    assert_eq!(value["chunks"], 381);
+
+    assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+}
+
+#[test]
+fn test_format_chunk_entry() {
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let entries = region::RegionFile::new(f).unwrap().chunk_entries().unwrap();
+    let entry = entries.iter().find(|e| (e.x, e.z) == (27, 6)).unwrap();
+    let path = Path::new("r.0.0.mca");
+
+    let csv = format_chunk_entry(path, entry, Format::Csv);
+    assert_eq!(
+        CHUNKS_CSV_HEADER,
+        "path,x,z,offset,sector_count,data_length,junk_bytes,timestamp,compression_type"
+    );
+    assert_eq!(
+        csv,
+        format!(
+            "r.0.0.mca,27,6,{},1,{},759,{},2",
+            entry.offset, entry.data_length, entry.timestamp
+        )
+    );
+    assert_eq!(csv.split(',').count(), CHUNKS_CSV_HEADER.split(',').count());
+    assert!(format_chunk_entry(path, entry, Format::Json).contains("\"junk_bytes\":759"));
+
+    // csv always lists the chunks, the other formats only with --chunks
+    let lists = |args: &[&str]| match Opts::parse_from(args).subcmd {
+        SubCommand::Info(subopts) => lists_chunks(&subopts),
+        _ => unreachable!(),
+    };
+    assert!(lists(&[
+        "mccompress",
+        "info",
+        "--format",
+        "csv",
+        "r.0.0.mca"
+    ]));
+    assert!(lists(&["mccompress", "info", "--chunks", "r.0.0.mca"]));
+    assert!(!lists(&[
+        "mccompress",
+        "info",
+        "--format",
+        "json",
+        "r.0.0.mca"
+    ]));
+    assert!(!lists(&["mccompress", "info", "r.0.0.mca"]));
+}
+
+#[test]
+fn test_format_header_dump() {
+    let file = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = region::RegionFile::new(File::open("tests/data/r.0.0.mca").unwrap()).unwrap();
+    let header = region.header_bytes().unwrap();
+    assert_eq!(&header[..], &file[..8192]);
+
+    let dump = format_header_dump(&header);
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(lines.len(), 512);
+
+    // the entry of (5, 3) is the second one on its line, in both tables
+    assert_eq!(
+        lines[25],
+        format!(
+            "0190  offsets     100-103  x= 4-7  z=3   {}",
+            file[0x190..0x1a0]
+                .chunks(4)
+                .map(|e| format!("{:02x} {:02x} {:02x} {:02x}", e[0], e[1], e[2], e[3]))
+                .collect::<Vec<_>>()
+                .join("  ")
+        )
+    );
+    assert!(lines[25].contains("  00 00 9c 01  "));
+    assert!(lines[256 + 25].starts_with("1190  timestamps  100-103  x= 4-7  z=3   "));
+    let ts = 1383443712u32.to_be_bytes();
+    assert!(lines[256 + 25].contains(&format!(
+        "  {:02x} {:02x} {:02x} {:02x}  ",
+        ts[0], ts[1], ts[2], ts[3]
+    )));
+}
+
+#[test]
+fn test_format_recompress() {
+    let stats = region::RecompressStats {
+        bytes_before: 1000,
+        bytes_after: 800,
+        chunks_recompressed: 3,
+        chunks_skipped: 1,
+        failed_chunks: Vec::new(),
+        ..Default::default()
+    };
+    let path = Path::new("r.0.0.mca");
+
+    assert_eq!(
+        format_recompress(path, &stats, Format::Json),
+        "{\"path\":\"r.0.0.mca\",\"bytes_before\":1000,\"bytes_after\":800,\"chunks_recompressed\":3,\"chunks_skipped\":1,\"failed_chunks\":[],\"sectors_before\":0,\"sectors_after\":0}"
+    );
+    assert_eq!(
+        format_recompress(path, &stats, Format::Csv),
+        "r.0.0.mca,1000,800,3,1,0"
+    );
+    assert_eq!(
+        format_recompress(path, &stats, Format::Text),
+        "Processed r.0.0.mca: 1000 -> 800 bytes, 3 chunks recompressed, 1 skipped, 0 failed"
+    );
+
+    let stats = region::RecompressStats {
+        failed_chunks: vec![(14, 10), (3, 4)],
+        ..stats
+    };
+    assert_eq!(
+        format_recompress(path, &stats, Format::Json),
+        "{\"path\":\"r.0.0.mca\",\"bytes_before\":1000,\"bytes_after\":800,\"chunks_recompressed\":3,\"chunks_skipped\":1,\"failed_chunks\":[[14,10],[3,4]],\"sectors_before\":0,\"sectors_after\":0}"
+    );
+    assert_eq!(
+        format_recompress(path, &stats, Format::Csv),
+        "r.0.0.mca,1000,800,3,1,2"
+    );
+    assert_eq!(
+        format_recompress(path, &stats, Format::Text),
+        "Processed r.0.0.mca: 1000 -> 800 bytes, 3 chunks recompressed, 1 skipped, 2 failed: (14, 10) (3, 4)"
+    );
+}
+
+#[test]
+fn test_junk_report() {
+    let (chunks, bytes) = junk_report(Path::new("tests/data/r.0.0.mca")).unwrap();
+    assert_eq!(chunks, 2);
+    assert_eq!(bytes, 759 + 1019);
+
+    // cleaning up a single file should get rid of all of it
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    run(Opts::parse_from([
+        "mccompress",
+        "cleanup",
+        "--jobs",
+        "1",
+        path.to_str().unwrap(),
+    ]));
+    assert_eq!(junk_report(&path).unwrap(), (0, 0));
+}
+
+#[cfg(feature = "nbt")]
+#[test]
+fn test_prune() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+
+    run(Opts::parse_from([
+        "mccompress",
+        "prune",
+        "--inhabited-below",
+        "100",
+        path.to_str().unwrap(),
+    ]));
+
+    let f = File::open(&path).unwrap();
+    let region = region::RegionFile::new(f).unwrap();
+    assert_eq!(region.count_chunks(), 381 - 341);
+    assert!(region.chunk_exists(27, 12));
+    assert!(!region.chunk_exists(27, 13));
+}
+
+#[cfg(feature = "nbt")]
+#[test]
+fn test_heaviest_chunks() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in &["r.0.0.mca", "r.0.1.mca"] {
+        std::fs::copy("tests/data/r.0.0.mca", dir.path().join(name)).unwrap();
+    }
+
+    let heaviest = heaviest_chunks(&[dir.path().to_path_buf()], 3, &mut 0);
+    let found: Vec<_> = heaviest
+        .iter()
+        .map(|count| {
+            let name = count.path.file_name().unwrap().to_str().unwrap();
+            (name, count.chunk, count.block_entities, count.entities)
+        })
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            ("r.0.0.mca", (13, 7), 0, 8),
+            ("r.0.0.mca", (6, 16), 0, 8),
+            ("r.0.1.mca", (13, 7), 0, 8),
+        ]
+    );
+}
+
+#[test]
+fn test_repair() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    let mut data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+
+    // point (0, 0) past the end of the file
+    data[..4].copy_from_slice(&(0xffff_u32 << 8 | 1).to_be_bytes());
+    std::fs::write(&path, &data).unwrap();
+
+    run(Opts::parse_from([
+        "mccompress",
+        "repair",
+        "--backup",
+        path.to_str().unwrap(),
+    ]));
+
+    let region = region::RegionFile::new(File::open(&path).unwrap()).unwrap();
+    assert_eq!(region.count_chunks(), 380);
+    assert!(!region.chunk_exists(0, 0));
+    assert_eq!(
+        std::fs::read(dir.path().join("r.0.0.mca.bak")).unwrap(),
+        data
+    );
+}
+
+#[test]
+fn test_defrag() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+
+    // deleting chunks leaves their sectors behind as holes
+    let mut region = region::RegionFile::from_bytes(data).unwrap();
+    region.delete_chunk(0, 0).unwrap();
+    region.delete_chunk(1, 0).unwrap();
+    let fragmented = region.into_inner().into_inner();
+    std::fs::write(&path, &fragmented).unwrap();
+
+    run(Opts::parse_from([
+        "mccompress",
+        "defrag",
+        "--backup",
+        path.to_str().unwrap(),
+    ]));
+
+    // both holes are gone, as well as the spare sector of (1, 10)
+    let defragged = std::fs::read(&path).unwrap();
+    assert_eq!(defragged.len(), fragmented.len() - 3 * 4096);
+    let mut region = region::RegionFile::new(io::Cursor::new(defragged)).unwrap();
+    let mut original = region::RegionFile::new(io::Cursor::new(fragmented.clone())).unwrap();
+    assert_eq!(region.count_chunks(), 379);
+    assert_eq!(
+        region.content_digest().unwrap(),
+        original.content_digest().unwrap()
+    );
+    assert_eq!(
+        std::fs::read(dir.path().join("r.0.0.mca.bak")).unwrap(),
+        fragmented
+    );
+
+    let stats = region::RecompressStats {
+        sectors_before: 398,
+        sectors_after: 395,
+        ..Default::default()
+    };
+    assert_eq!(
+        format_defrag(&path, &stats),
+        format!("{}: reclaimed 12 KiB, 398 -> 395 sectors", path.display())
+    );
+    let stats = region::RecompressStats {
+        sectors_before: 395,
+        sectors_after: 395,
+        ..Default::default()
+    };
+    assert_eq!(
+        format_defrag(&path, &stats),
+        format!("{}: nothing to reclaim, 395 sectors", path.display())
+    );
+}
+
+#[test]
+fn test_extract() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("chunk.nbt");
+
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let expected = region::RegionFile::new(f)
+        .unwrap()
+        .load_chunk(14, 10)
+        .unwrap();
+
+    run(Opts::parse_from([
+        "mccompress",
+        "extract",
+        "tests/data/r.0.0.mca",
+        "--chunk",
+        "14",
+        "10",
+        "--out",
+        out.to_str().unwrap(),
+    ]));
+
+    // a single gzip member, just like the .nbt files the game writes itself
+    let gzipped = std::fs::read(&out).unwrap();
+    assert!(gzipped.starts_with(&[0x1f, 0x8b]));
+    let mut decoder = flate2::read::GzDecoder::new(&gzipped[..]);
+    let mut data = Vec::new();
+    io::Read::read_to_end(&mut decoder, &mut data).unwrap();
+    assert_eq!(data, expected);
+    assert!(decoder.into_inner().is_empty());
+
+    run(Opts::parse_from([
+        "mccompress",
+        "extract",
+        "tests/data/r.0.0.mca",
+        "--chunk",
+        "14",
+        "10",
+        "--out",
+        out.to_str().unwrap(),
+        "--raw",
+    ]));
+    assert_eq!(std::fs::read(&out).unwrap(), expected);
+
+    assert!(Opts::try_parse_from([
+        "mccompress",
+        "extract",
+        "tests/data/r.0.0.mca",
+        "--chunk",
+        "32",
+        "0",
+        "--out",
+        "chunk.nbt",
+    ])
+    .is_err());
+}
+
+#[test]
+fn test_import() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    let nbt = dir.path().join("chunk.nbt");
+
+    // move chunk 14 10 over to 15 15 by extracting and importing it again
+    extract(Path::new("tests/data/r.0.0.mca"), 14, 10, &nbt, false, None).unwrap();
+    run(Opts::parse_from([
+        "mccompress",
+        "import",
+        path.to_str().unwrap(),
+        "--chunk",
+        "15",
+        "15",
+        "--in",
+        nbt.to_str().unwrap(),
+        "--timestamp",
+        "1234",
+    ]));
+
+    let mut region = region::RegionFile::new(File::open(&path).unwrap()).unwrap();
+    assert_eq!(
+        region.load_chunk(15, 15).unwrap(),
+        region.load_chunk(14, 10).unwrap()
+    );
+    assert_eq!(region.get_chunk_timestamp(15, 15), Some(1234));
+
+    // without a timestamp it should be set to the current time
+    extract(Path::new("tests/data/r.0.0.mca"), 0, 0, &nbt, true, None).unwrap();
+    run(Opts::parse_from([
+        "mccompress",
+        "import",
+        path.to_str().unwrap(),
+        "--chunk",
+        "14",
+        "10",
+        "--in",
+        nbt.to_str().unwrap(),
+    ]));
+
+    let mut region = region::RegionFile::new(File::open(&path).unwrap()).unwrap();
+    assert_eq!(
+        region.load_chunk(14, 10).unwrap(),
+        std::fs::read(&nbt).unwrap()
+    );
+    assert!(region.get_chunk_timestamp(14, 10).unwrap() > 1600000000);
+}
+
+/// Deterministic bytes that zlib can't make any smaller
+#[cfg(test)]
+fn incompressible(len: usize) -> Vec<u8> {
+    let mut state = 0x2545f491_u32;
+    (0..len)
+        .map(|_| {
+            // xorshift
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_mcc_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    let mcc_dir = dir.path().join("mcc");
+    fs::create_dir(&mcc_dir).unwrap();
+
+    // too large to fit in the region file even after compressing it
+    let large = incompressible(1100 * 1024);
+    let nbt = dir.path().join("chunk.nbt");
+    fs::write(&nbt, &large).unwrap();
+
+    run(Opts::parse_from([
+        "mccompress",
+        "import",
+        path.to_str().unwrap(),
+        "--chunk",
+        "14",
+        "10",
+        "--in",
+        nbt.to_str().unwrap(),
+        "--mcc-dir",
+        mcc_dir.to_str().unwrap(),
+    ]));
+    assert!(mcc_dir.join("c.14.10.mcc").exists());
+    assert!(!dir.path().join("c.14.10.mcc").exists());
+
+    let out = dir.path().join("out.nbt");
+    run(Opts::parse_from([
+        "mccompress",
+        "extract",
+        path.to_str().unwrap(),
+        "--chunk",
+        "14",
+        "10",
+        "--out",
+        out.to_str().unwrap(),
+        "--raw",
+        "--mcc-dir",
+        mcc_dir.to_str().unwrap(),
+    ]));
+    assert_eq!(fs::read(&out).unwrap(), large);
+    assert_eq!(
+        verify_report(&path, Some(&mcc_dir), region::ChunkRange::ALL).unwrap(),
+        (381, vec![], 0)
+    );
+}
+
+#[test]
+fn test_output_dir_mcc() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    let mcc_dir = tempfile::tempdir().unwrap();
+    let path = input.path().join("region").join("r.0.0.mca");
+    fs::create_dir(path.parent().unwrap()).unwrap();
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+
+    let large = incompressible(1100 * 1024);
+    let nbt = input.path().join("chunk.nbt");
+    fs::write(&nbt, &large).unwrap();
+    import(&path, 14, 10, &nbt, 1600000000, Some(mcc_dir.path())).unwrap();
+    let mcc = fs::read(mcc_dir.path().join("c.14.10.mcc")).unwrap();
+    let original = fs::read(&path).unwrap();
+
+    run(Opts::parse_from([
+        Path::new("mccompress"),
+        Path::new("recompress"),
+        Path::new("--jobs"),
+        Path::new("1"),
+        Path::new("--output-dir"),
+        output.path(),
+        Path::new("--mcc-dir"),
+        mcc_dir.path(),
+        input.path(),
+    ]));
+
+    // the chunk is still too large for the region, so its .mcc file comes along to the copy
+    let written = output.path().join("region").join("r.0.0.mca");
+    assert_eq!(
+        fs::read(written.with_file_name("c.14.10.mcc")).unwrap(),
+        mcc
+    );
+    assert_eq!(
+        verify_report(&written, None, region::ChunkRange::ALL).unwrap(),
+        (381, vec![], 0)
+    );
+    let mut copy = region::RegionFile::new(File::open(&written).unwrap()).unwrap();
+    copy.set_region_path(&written);
+    assert_eq!(copy.load_chunk(14, 10).unwrap(), large);
+
+    // and the source is left as it was
+    assert_eq!(fs::read(&path).unwrap(), original);
+    assert_eq!(fs::read(mcc_dir.path().join("c.14.10.mcc")).unwrap(), mcc);
+}
+
+#[test]
+fn test_compact_internalizes() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    let mcc_dir = dir.path().join("mcc");
+    fs::create_dir(&mcc_dir).unwrap();
+
+    let large = incompressible(1100 * 1024);
+    let nbt = dir.path().join("chunk.nbt");
+    fs::write(&nbt, &large).unwrap();
+
+    // the chunk is moved out and then shrinks, like when the game saved it again after some
+    // blocks were removed
+    let small = vec![7; 100 * 1024];
+    let externalize = |mcc: &Path, extra: &[&str]| {
+        let mut args = vec![
+            "mccompress",
+            "import",
+            path.to_str().unwrap(),
+            "--chunk",
+            "14",
+            "10",
+            "--in",
+            nbt.to_str().unwrap(),
+        ];
+        args.extend_from_slice(extra);
+        run(Opts::parse_from(args));
+
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&small).unwrap();
+        fs::write(mcc, encoder.finish().unwrap()).unwrap();
+    };
+    let internalized = |mcc: &Path| {
+        assert!(!mcc.exists());
+        let mut region = region::RegionFile::new(File::open(&path).unwrap()).unwrap();
+        assert!(region.external_chunks().unwrap().is_empty());
+        assert_eq!(region.load_chunk(14, 10).unwrap(), small);
+        assert_eq!(region.verify(), vec![]);
+    };
+
+    let mcc = mcc_dir.join("c.14.10.mcc");
+    externalize(&mcc, &["--mcc-dir", mcc_dir.to_str().unwrap()]);
+    run(Opts::parse_from([
+        "mccompress",
+        "defrag",
+        "--mcc-dir",
+        mcc_dir.to_str().unwrap(),
+        "--quiet",
+        path.to_str().unwrap(),
+    ]));
+    internalized(&mcc);
+
+    let mcc = dir.path().join("c.14.10.mcc");
+    externalize(&mcc, &[]);
+    run(Opts::parse_from([
+        "mccompress",
+        "recompress",
+        "--safe",
+        "--quiet",
+        path.to_str().unwrap(),
+    ]));
+    internalized(&mcc);
+}
+
+#[test]
+fn test_world_folder() {
+    let world = tempfile::tempdir().unwrap();
+    let regions = [
+        "region/r.0.0.mca",
+        "entities/r.0.0.mca",
+        "poi/r.0.0.mca",
+        "DIM-1/region/r.0.0.mca",
+        "DIM-1/entities/r.-1.0.mca",
+        "DIM1/region/r.0.-1.mca",
+    ];
+    for region in &regions {
+        let path = world.path().join(region);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    }
+    std::fs::copy(
+        "tests/data/r.0.0.mcr",
+        world.path().join("region/r.0.0.mcr"),
+    )
+    .unwrap();
+    std::fs::write(world.path().join("level.dat"), b"not a region").unwrap();
+    std::fs::write(world.path().join("DIM-1/region/notes.txt"), b"hello").unwrap();
+
+    let mut files: Vec<PathBuf> = region_files(&[world.path().to_path_buf()], &mut 0)
+        .into_iter()
+        .map(|(_, e)| e.path().strip_prefix(world.path()).unwrap().to_path_buf())
+        .collect();
+    files.sort();
+
+    let mut expected: Vec<PathBuf> = regions.iter().map(PathBuf::from).collect();
+    expected.push(PathBuf::from("region/r.0.0.mcr"));
+    expected.sort();
+    assert_eq!(files, expected);
+
+    run(Opts::parse_from([
+        "mccompress",
+        "cleanup",
+        "--jobs",
+        "1",
+        world.path().to_str().unwrap(),
+    ]));
+    for region in &regions {
+        assert_eq!(junk_report(&world.path().join(region)).unwrap(), (0, 0));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_region_files_non_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let nested = dir
+        .path()
+        .join(OsStr::from_bytes(b"DIM\xff\xfe"))
+        .join("region");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::copy("tests/data/r.0.0.mca", nested.join("r.0.0.mca")).unwrap();
+    std::fs::copy(
+        "tests/data/r.0.0.mca",
+        nested.join(OsStr::from_bytes(b"r.\xff.mca")),
+    )
+    .unwrap();
+    std::fs::write(nested.join("r.0.0.mca.bak"), b"backup").unwrap();
+
+    let mut files: Vec<PathBuf> = region_files(&[dir.path().to_path_buf()], &mut 0)
+        .into_iter()
+        .map(|(_, e)| e.into_path())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            nested.join("r.0.0.mca"),
+            nested.join(OsStr::from_bytes(b"r.\xff.mca"))
+        ]
+    );
+}
+
+#[test]
+fn test_preserve_mtime() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    let old = FileTime::from_unix_time(1383443713, 0);
+    let mtime = |path: &Path| FileTime::from_last_modification_time(&fs::metadata(path).unwrap());
+
+    for args in &[
+        &["cleanup"][..],
+        &["recompress", "--level", "best"][..],
+        &["recompress", "--level", "fast", "--safe"][..],
+    ] {
+        for preserve in &[true, false] {
+            std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+            filetime::set_file_mtime(&path, old).unwrap();
+
+            let mut cmd = vec!["mccompress"];
+            cmd.extend_from_slice(args);
+            cmd.extend_from_slice(&["--jobs", "1", path.to_str().unwrap()]);
+            if *preserve {
+                cmd.push("--preserve-mtime");
+            }
+            run(Opts::parse_from(cmd));
+
+            assert_eq!(mtime(&path) == old, *preserve, "{:?} {}", args, preserve);
+        }
+    }
+}
+
+#[test]
+fn test_empty_region_untouched() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    let old = FileTime::from_unix_time(1383443713, 0);
+    let mtime = |path: &Path| FileTime::from_last_modification_time(&fs::metadata(path).unwrap());
+
+    // just a header, with some leftovers of chunks that were deleted
+    let mut empty = vec![0; 8192];
+    empty.extend_from_slice(&[0xff; 4096]);
+    for args in &[
+        &["cleanup"][..],
+        &["cleanup", "--zero-fill"][..],
+        &["recompress", "--level", "best"][..],
+        &["recompress", "--level", "fast", "--safe"][..],
+    ] {
+        std::fs::write(&path, &empty).unwrap();
+        filetime::set_file_mtime(&path, old).unwrap();
+
+        let mut cmd = vec!["mccompress"];
+        cmd.extend_from_slice(args);
+        cmd.extend_from_slice(&["--jobs", "1", "--backup", path.to_str().unwrap()]);
+        run(Opts::parse_from(cmd));
+
+        assert_eq!(std::fs::read(&path).unwrap(), empty, "{:?}", args);
+        assert_eq!(mtime(&path), old, "{:?}", args);
+        assert!(!dir.path().join("r.0.0.mca.bak").exists(), "{:?}", args);
+    }
+}
+
+#[test]
+fn test_locked_files_are_skipped() {
+    let world = tempfile::tempdir().unwrap();
+    let path = world.path().join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    let original = std::fs::read(&path).unwrap();
+
+    let cleanup = |force: bool| {
+        let mut cmd = vec!["mccompress", "cleanup", "--jobs", "1"];
+        if force {
+            cmd.push("--force");
+        }
+        cmd.push(world.path().to_str().unwrap());
+        run(Opts::parse_from(cmd));
+    };
+
+    // a lock on the region file itself
+    let lock = File::open(&path).unwrap();
+    lock.lock_exclusive().unwrap();
+    cleanup(false);
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+    lock.unlock().unwrap();
+
+    // a lock on the session.lock of the world
+    let session = File::create(world.path().join("session.lock")).unwrap();
+    session.lock_exclusive().unwrap();
+    cleanup(false);
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+
+    cleanup(true);
+    assert_eq!(junk_report(&path).unwrap(), (0, 0));
+}
+
+/// Set for the child process of `test_fcntl_locks_are_detected`, to the world it should lock
+#[cfg(all(test, unix))]
+const FCNTL_LOCK_CHILD: &str = "MCCOMPRESS_FCNTL_LOCK_CHILD";
+
+/// Holds fcntl locks the way a server does when started by `test_fcntl_locks_are_detected`, as
+/// those locks only conflict with other processes.  They're held until stdin is closed
+#[cfg(unix)]
+#[test]
+fn fcntl_lock_child() {
+    use rustix::fs::{fcntl_lock, FlockOperation};
+
+    let world = match std::env::var_os(FCNTL_LOCK_CHILD) {
+        Some(world) => PathBuf::from(world),
+        None => return,
+    };
+    let open = |path: PathBuf| {
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        fcntl_lock(&f, FlockOperation::NonBlockingLockExclusive).unwrap();
+        f
+    };
+    let _session = open(world.join("session.lock"));
+    let _region = open(world.join("region").join("r.0.0.mca"));
+
+    println!("LOCKED");
+    io::stdout().flush().unwrap();
+    io::Read::read_to_end(&mut io::stdin(), &mut Vec::new()).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_fcntl_locks_are_detected() {
+    use std::io::BufRead;
+    use std::process::{Command, Stdio};
+
+    let world = tempfile::tempdir().unwrap();
+    let region_dir = world.path().join("region");
+    let path = region_dir.join("r.0.0.mca");
+    std::fs::create_dir(&region_dir).unwrap();
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+    File::create(world.path().join("session.lock")).unwrap();
+    let original = std::fs::read(&path).unwrap();
+
+    let mut child = Command::new(std::env::current_exe().unwrap())
+        .args([
+            "--exact",
+            "fcntl_lock_child",
+            "--nocapture",
+            "--test-threads=1",
+        ])
+        .env(FCNTL_LOCK_CHILD, world.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // the harness puts the name of the test in front of it on the same line, the rest of its
+    // output is left in the pipe so it can still finish up
+    let mut stdout = io::BufReader::new(child.stdout.take().unwrap()).lines();
+    assert!(stdout
+        .by_ref()
+        .map(|line| line.unwrap())
+        .any(|line| line.ends_with("LOCKED")));
+
+    assert!(world_in_use(world.path()));
+    assert!(world_in_use(&region_dir));
+    assert!(world_in_use(&path));
+    assert!(lock_region(&path, false).is_err());
+    for input in [world.path(), &region_dir, &path] {
+        run(Opts::parse_from([
+            Path::new("mccompress"),
+            Path::new("cleanup"),
+            Path::new("--jobs"),
+            Path::new("1"),
+            input,
+        ]));
+        assert_eq!(std::fs::read(&path).unwrap(), original, "{:?}", input);
+    }
+
+    // both locks are gone with the process
+    drop(child.stdin.take());
+    assert!(child.wait().unwrap().success());
+    drop(stdout);
+    assert!(!world_in_use(&path));
+    assert!(lock_region(&path, false).is_ok());
+}
+
+#[test]
+fn test_region_files_sorted() {
+    let dir = tempfile::tempdir().unwrap();
+    let names = [
+        "r.1.0.mca",
+        "r.0.0.mca",
+        "a/r.5.5.mca",
+        "r.-1.0.mca",
+        "b/c/r.0.0.mca",
+    ];
+    for name in &names {
+        let path = dir.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::copy("tests/data/r.0.0.mca", path).unwrap();
+    }
+
+    let files: Vec<PathBuf> = region_files(&[dir.path().to_path_buf()], &mut 0)
+        .into_iter()
+        .map(|(_, e)| e.path().strip_prefix(dir.path()).unwrap().to_path_buf())
+        .collect();
+    let expected: Vec<PathBuf> = [
+        "a/r.5.5.mca",
+        "b/c/r.0.0.mca",
+        "r.-1.0.mca",
+        "r.0.0.mca",
+        "r.1.0.mca",
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(files, expected);
+}
+
+/// Overwrites the start of the data of chunk (14, 10) in the region file at `path` with garbage
+#[cfg(test)]
+fn corrupt_chunk_14_10(path: &Path) {
+    let mut data = std::fs::read(path).unwrap();
+    let idx = (14 + 10 * 32) * 4;
+    let sector = u32::from_be_bytes([0, data[idx], data[idx + 1], data[idx + 2]]) as usize;
+    for b in &mut data[sector * 4096 + 5..sector * 4096 + 105] {
+        *b = 0xff;
+    }
+    std::fs::write(path, data).unwrap();
+}
+
+#[test]
+fn test_corrupt_chunk() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in &["r.0.0.mca", "r.1.0.mca"] {
+        std::fs::copy("tests/data/r.0.0.mca", dir.path().join(name)).unwrap();
+    }
+
+    let corrupt = dir.path().join("r.1.0.mca");
+    corrupt_chunk_14_10(&corrupt);
+
+    assert_eq!(
+        verify_report(&dir.path().join("r.0.0.mca"), None, region::ChunkRange::ALL).unwrap(),
+        (381, vec![], 0)
+    );
+
+    // a file that runs on past its last sector
+    let padded = dir.path().join("r.5.5.mca");
+    let mut data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    data.extend_from_slice(&[0; 17]);
+    std::fs::write(&padded, data).unwrap();
+    assert_eq!(
+        verify_report(&padded, None, region::ChunkRange::ALL).unwrap(),
+        (381, vec![], 17)
+    );
+    std::fs::remove_file(&padded).unwrap();
+    assert_eq!(
+        verify_report(&corrupt, None, region::ChunkRange::ALL).unwrap(),
+        (381, vec![(14, 10)], 0)
+    );
+    run(Opts::parse_from([
+        "mccompress",
+        "verify",
+        "--quiet",
+        dir.path().to_str().unwrap(),
+    ]));
+
+    // recompressing carries on past the corrupt chunk and leaves it alone
+    run(Opts::parse_from([
+        "mccompress",
+        "recompress",
+        "--level",
+        "best",
+        "--jobs",
+        "1",
+        "--quiet",
+        dir.path().to_str().unwrap(),
+    ]));
+    assert_eq!(
+        verify_report(&corrupt, None, region::ChunkRange::ALL).unwrap(),
+        (381, vec![(14, 10)], 0)
+    );
+    let mut options = region::RecompressOptions::new(Compression::best());
+    options.continue_on_error = true;
+    let mut region = region::RegionFile::new(File::open(&corrupt).unwrap()).unwrap();
+    let mut out = io::Cursor::new(Vec::new());
+    let stats = region
+        .compact(&mut out, Some(&options), region::CompactOrder::Index)
+        .unwrap();
+    assert_eq!(stats.failed_chunks, vec![(14, 10)]);
+    assert_eq!(stats.chunks_skipped, 380);
+}
+
+#[test]
+fn test_verify_first() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in &["r.0.0.mca", "r.1.0.mca"] {
+        std::fs::copy("tests/data/r.0.0.mca", dir.path().join(name)).unwrap();
+    }
+
+    let corrupt = dir.path().join("r.1.0.mca");
+    corrupt_chunk_14_10(&corrupt);
+    let data = std::fs::read(&corrupt).unwrap();
+
+    assert_eq!(verify_first_failure(&dir.path().join("r.0.0.mca")), None);
+    assert_eq!(
+        verify_first_failure(&corrupt).unwrap(),
+        "1 chunks failed to verify: (14, 10)"
+    );
+    assert!(verify_first_failure(&dir.path().join("r.2.0.mca"))
+        .unwrap()
+        .starts_with("it couldn't be verified"));
+
+    run(Opts::parse_from([
+        "mccompress",
+        "recompress",
+        "--level",
+        "best",
+        "--verify-first",
+        "--quiet",
+        dir.path().to_str().unwrap(),
+    ]));
+
+    // the corrupt file wasn't touched at all, the clean one was recompressed as usual
+    assert_eq!(std::fs::read(&corrupt).unwrap(), data);
+    assert_ne!(
+        std::fs::read(dir.path().join("r.0.0.mca")).unwrap(),
+        std::fs::read("tests/data/r.0.0.mca").unwrap()
+    );
+    assert_eq!(
+        verify_report(&dir.path().join("r.0.0.mca"), None, region::ChunkRange::ALL).unwrap(),
+        (381, vec![], 0)
+    );
+}
+
+#[test]
+fn test_log_levels() {
+    use log::{Level, Log, Metadata};
+
+    let enabled = |args: &[&str], level: Level| {
+        let opts = Opts::parse_from(args);
+        logger(opts.quiet, opts.verbose).enabled(&Metadata::builder().level(level).build())
+    };
+
+    // the per file success messages are info, errors and failed chunks are still shown when quiet
+    let quiet = ["mccompress", "recompress", "--quiet", "world"];
+    assert!(!enabled(&quiet, Level::Info));
+    assert!(enabled(&quiet, Level::Warn));
+    assert!(enabled(&quiet, Level::Error));
+
+    let normal = ["mccompress", "cleanup", "world"];
+    assert!(enabled(&normal, Level::Info));
+    assert!(!enabled(&normal, Level::Debug));
+
+    let verbose = ["mccompress", "-v", "recompress", "world"];
+    assert!(enabled(&verbose, Level::Debug));
+
+    assert!(Opts::try_parse_from(["mccompress", "-q", "-v", "verify", "world"]).is_err());
 }
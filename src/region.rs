@@ -1,6 +1,10 @@
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use flate2;
-use std::io;
+use byteorder::{BigEndian, WriteBytesExt};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 pub enum Error {
@@ -9,6 +13,56 @@ pub enum Error {
         /// Compression type byte from the format.
         compression_type: u8,
     },
+    MissingChunk {
+        x: u8,
+        z: u8,
+    },
+    /// Recompressed chunk data didn't decompress to the same bytes as the original, only
+    /// returned when `RecompressOptions::verify` is set
+    RoundTripMismatch,
+    /// Two chunks point at the same sector in the header, recompressing them in place would
+    /// corrupt both
+    DuplicateOffset {
+        first: (u8, u8),
+        second: (u8, u8),
+    },
+    ChunkTooLarge {
+        /// The amount of sectors the chunk would need, at most 255 fit in the header
+        sectors: usize,
+    },
+    /// Recompressing would have made the chunks of the region take up more bytes than before,
+    /// only returned when `RecompressOptions::discard_if_grown` is set.  The region is left as
+    /// it was.
+    RegionGrew {
+        bytes_before: usize,
+        bytes_after: usize,
+    },
+    /// The chunk is stored in a separate `.mcc` file, but no directory to find it in was set
+    /// with `RegionFile::set_external_dir`
+    ExternalChunk {
+        x: u8,
+        z: u8,
+    },
+    /// The length stored in front of the chunk's data is 0 or doesn't fit in the sectors the
+    /// header gives the chunk, or its compressed data ends before the stream is complete.  Also
+    /// returned for chunks whose header entry has an offset but no sectors.
+    CorruptChunk {
+        x: u8,
+        z: u8,
+    },
+    /// The file is too short to even hold the offset and timestamp tables, like an empty file or a
+    /// download that was cut off
+    NotARegionFile {
+        /// The length of the file in bytes
+        len: u64,
+    },
+    #[cfg(feature = "nbt")]
+    Nbt(fastnbt::error::Error),
+    #[cfg(feature = "nbt")]
+    MissingTag {
+        /// Name of the tag that was expected in the chunk
+        name: &'static str,
+    },
 }
 
 impl From<io::Error> for Error {
@@ -17,6 +71,586 @@ impl From<io::Error> for Error {
     }
 }
 
+#[cfg(feature = "nbt")]
+impl From<fastnbt::error::Error> for Error {
+    fn from(err: fastnbt::error::Error) -> Error {
+        Error::Nbt(err)
+    }
+}
+
+/// The size of the sectors region files are divided in, as the game writes them.  Chunks take up
+/// a whole number of sectors.
+pub const SECTOR_SIZE: usize = 4096;
+
+/// The size of the offset table, which the timestamp table follows right after
+const TABLE_LEN: u64 = 4096;
+
+/// The size of both tables together, which is where the chunks can start
+const HEADER_LEN: u64 = 2 * TABLE_LEN;
+
+/// Set in the compression type byte of chunks whose data is stored in a separate `.mcc` file,
+/// because it needs more than the 255 sectors the header can describe
+pub const EXTERNAL_FLAG: u8 = 0x80;
+
+/// The ways chunk data can be compressed in a region file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompressionFormat {
+    Gzip = 1,
+    Zlib = 2,
+    Uncompressed = 3,
+    /// Only supported when built with the `zstd` feature
+    Zstd = 4,
+}
+
+impl TryFrom<u8> for CompressionFormat {
+    type Error = Error;
+
+    fn try_from(compression_type: u8) -> Result<CompressionFormat, Error> {
+        match compression_type {
+            1 => Ok(CompressionFormat::Gzip),
+            2 => Ok(CompressionFormat::Zlib),
+            3 => Ok(CompressionFormat::Uncompressed),
+            4 => Ok(CompressionFormat::Zstd),
+            _ => Err(Error::UnsupportedCompressionFormat { compression_type }),
+        }
+    }
+}
+
+impl CompressionFormat {
+    /// The compression type byte used for this format in region files
+    pub fn to_byte(self) -> u8 {
+        self as u8
+    }
+}
+
+impl From<CompressionFormat> for u8 {
+    fn from(format: CompressionFormat) -> u8 {
+        format.to_byte()
+    }
+}
+
+/// Settings for recompressing chunks
+#[derive(Debug, Clone, Copy)]
+pub struct RecompressOptions {
+    /// The compression level chunks get recompressed with
+    pub level: flate2::Compression,
+
+    /// Chunks that would shrink by less than this amount of bytes are left as they are
+    pub min_saving: usize,
+
+    /// Chunks with less compressed data than this are left as they are without even trying,
+    /// they hardly ever get any smaller
+    pub min_chunk_bytes: usize,
+
+    /// Store chunks uncompressed (compression type 3) when compressing them doesn't make them smaller
+    pub allow_store: bool,
+
+    /// Try every level in `ADAPTIVE_LEVELS` on top of `level` and keep whichever turns out smallest
+    pub adaptive: bool,
+
+    /// Update the timestamp of every recompressed chunk to the current time, instead of keeping
+    /// the original one
+    pub touch: bool,
+
+    /// Leave chunks that can't be recompressed (because they're corrupt for example) as they are
+    /// and carry on with the rest, instead of failing on them
+    pub continue_on_error: bool,
+
+    /// Decompress every recompressed chunk again and compare it to the original data before
+    /// writing it back, at the cost of some extra cpu time
+    pub verify: bool,
+
+    /// Only recompress chunks with a timestamp after this one, the others are skipped
+    pub modified_after: Option<u32>,
+
+    /// Leave the region as it was when its chunks would take up more bytes after recompressing
+    /// than before, which can happen when recompressing with a lower level than they had.  In
+    /// place the chunks are kept in memory until the end to put them back.
+    pub discard_if_grown: bool,
+
+    /// Convert every chunk to this format while recompressing it, rather than keeping the format
+    /// it was in, `allow_store` doesn't apply then.  In place, chunks that would no longer fit in
+    /// their sectors are left as they are.
+    pub target_format: Option<CompressionFormat>,
+
+    /// Only recompress the chunks in this rectangle, the others are left as they are
+    pub chunk_range: Option<ChunkRange>,
+}
+
+/// The compression levels that are tried on every chunk in adaptive mode
+pub const ADAPTIVE_LEVELS: [u32; 2] = [6, 9];
+
+impl RecompressOptions {
+    pub fn new(level: flate2::Compression) -> RecompressOptions {
+        RecompressOptions {
+            level,
+            min_saving: 0,
+            min_chunk_bytes: 0,
+            allow_store: false,
+            adaptive: false,
+            touch: false,
+            continue_on_error: false,
+            verify: false,
+            modified_after: None,
+            discard_if_grown: false,
+            target_format: None,
+            chunk_range: None,
+        }
+    }
+
+    /// Whether the chunk at these coordinates with this timestamp should be recompressed at all
+    fn includes(&self, x: u8, z: u8, timestamp: u32) -> bool {
+        self.modified_after.is_none_or(|after| timestamp > after)
+            && self.chunk_range.is_none_or(|range| range.contains(x, z))
+    }
+
+    /// The compression levels chunks should be tried with
+    fn levels(&self) -> Vec<flate2::Compression> {
+        let mut levels = vec![self.level];
+        if self.adaptive {
+            levels.extend(ADAPTIVE_LEVELS.iter().map(|l| flate2::Compression::new(*l)));
+        }
+        levels
+    }
+
+    /// Recompresses the data of a chunk into `scratch`, returns the new compression type or `None`
+    /// if the result isn't worth writing back
+    fn apply(
+        &self,
+        format: CompressionFormat,
+        data: &[u8],
+        scratch: &mut Vec<u8>,
+    ) -> Result<Option<CompressionFormat>, Error> {
+        let mut source = io::Cursor::new(data);
+        self.apply_levels(&self.levels(), format, &mut source, 0, data.len(), scratch)
+    }
+
+    /// Same as `apply`, but recompresses with each of the given levels and keeps the smallest
+    /// result.  The compressed data is streamed from the `len` bytes at `start` in `source`, and
+    /// read again for every pass instead of being held in memory.
+    fn apply_levels<S>(
+        &self,
+        levels: &[flate2::Compression],
+        format: CompressionFormat,
+        source: &mut S,
+        start: u64,
+        len: usize,
+        scratch: &mut Vec<u8>,
+    ) -> Result<Option<CompressionFormat>, Error>
+    where
+        S: io::Read + io::Seek,
+    {
+        if len < self.min_chunk_bytes {
+            return Ok(None);
+        }
+
+        let (first, others) = match levels.split_first() {
+            Some(levels) => levels,
+            None => return Ok(None),
+        };
+        let target = self.target_format.unwrap_or(format);
+        recompress(
+            format,
+            target,
+            payload(source, start, len)?,
+            *first,
+            scratch,
+        )?;
+
+        // only the extra levels in adaptive mode need a second buffer
+        let mut attempt = Vec::new();
+        for level in others {
+            let data = payload(source, start, len)?;
+            recompress(format, target, data, *level, &mut attempt)?;
+            if attempt.len() < scratch.len() {
+                std::mem::swap(scratch, &mut attempt);
+            }
+        }
+
+        let original = format;
+        let mut format = target;
+        if self.allow_store && self.target_format.is_none() {
+            let raw = decompress(original, payload(source, start, len)?)?;
+            if raw.len() <= scratch.len() {
+                *scratch = raw;
+                format = CompressionFormat::Uncompressed;
+            }
+        }
+
+        if len.saturating_sub(scratch.len()) < self.min_saving {
+            return Ok(None);
+        }
+
+        // the chunk was most likely compressed like this already, so writing it is a waste
+        if format == original && scratch.len() == len {
+            return Ok(None);
+        }
+
+        if self.verify
+            && decompress(format, scratch.as_slice())?
+                != decompress(original, payload(source, start, len)?)?
+        {
+            return Err(Error::RoundTripMismatch);
+        }
+
+        Ok(Some(format))
+    }
+}
+
+/// Statistics about recompressed chunks
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecompressStats {
+    /// Total length of the chunk data before recompressing
+    pub bytes_before: usize,
+
+    /// Total length of the chunk data after recompressing
+    pub bytes_after: usize,
+
+    /// The amount of chunks that were recompressed and written back
+    pub chunks_recompressed: usize,
+
+    /// The amount of chunks that were left as they were
+    pub chunks_skipped: usize,
+
+    /// The coordinates of the chunks that couldn't be recompressed, these are left as they were
+    /// as well when continuing on errors
+    pub failed_chunks: Vec<(u8, u8)>,
+
+    /// The amount of sectors the region file took up before it was compacted, only filled in by
+    /// `compact`
+    pub sectors_before: usize,
+
+    /// The amount of sectors the compacted region file takes up, header included
+    pub sectors_after: usize,
+}
+
+impl RecompressStats {
+    fn recompressed(before: usize, after: usize) -> RecompressStats {
+        RecompressStats {
+            bytes_before: before,
+            bytes_after: after,
+            chunks_recompressed: 1,
+            chunks_skipped: 0,
+            failed_chunks: Vec::new(),
+            sectors_before: 0,
+            sectors_after: 0,
+        }
+    }
+
+    fn skipped(len: usize) -> RecompressStats {
+        RecompressStats {
+            bytes_before: len,
+            bytes_after: len,
+            chunks_recompressed: 0,
+            chunks_skipped: 1,
+            failed_chunks: Vec::new(),
+            sectors_before: 0,
+            sectors_after: 0,
+        }
+    }
+
+    fn failed(x: u8, z: u8, len: usize) -> RecompressStats {
+        RecompressStats {
+            bytes_before: len,
+            bytes_after: len,
+            chunks_recompressed: 0,
+            chunks_skipped: 0,
+            failed_chunks: vec![(x, z)],
+            sectors_before: 0,
+            sectors_after: 0,
+        }
+    }
+}
+
+impl std::ops::AddAssign for RecompressStats {
+    fn add_assign(&mut self, other: RecompressStats) {
+        self.bytes_before += other.bytes_before;
+        self.bytes_after += other.bytes_after;
+        self.chunks_recompressed += other.chunks_recompressed;
+        self.chunks_skipped += other.chunks_skipped;
+        self.failed_chunks.extend(other.failed_chunks);
+        self.sectors_before += other.sectors_before;
+        self.sectors_after += other.sectors_after;
+    }
+}
+
+/// What cleaning up the junk in a region file did
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CleanupStats {
+    /// The amount of chunks that had junk after their data
+    pub chunks_cleaned: usize,
+
+    /// The amount of bytes that were zeroed
+    pub bytes_cleaned: usize,
+
+    /// The amount of chunks that were left alone, because they were clean already or too old
+    pub chunks_skipped: usize,
+}
+
+/// A summary of the contents of a region file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InfoReport {
+    /// The amount of chunks present
+    pub chunks: usize,
+
+    /// The size of the file in sectors
+    pub total_sectors: usize,
+
+    /// The amount of sectors in use by the header and the chunks, the difference with
+    /// `total_sectors` are unused gaps in the file
+    pub used_sectors: usize,
+
+    /// The total length of the chunk data, including the 4 byte length fields
+    pub data_bytes: usize,
+
+    /// The total size of the sectors allocated to chunks
+    pub allocated_bytes: usize,
+
+    /// How many chunks there are for each compression type
+    pub compression_types: BTreeMap<u8, usize>,
+
+    /// The timestamp of the least recently modified chunk
+    pub oldest_timestamp: Option<u32>,
+
+    /// The timestamp of the most recently modified chunk
+    pub newest_timestamp: Option<u32>,
+
+    /// How many chunks there are for each DataVersion
+    #[cfg(feature = "nbt")]
+    pub data_versions: BTreeMap<i32, usize>,
+
+    /// The amount of chunks from before the DataVersion tag was introduced
+    #[cfg(feature = "nbt")]
+    pub chunks_without_data_version: usize,
+}
+
+/// A rectangle of chunks within a region by their local coordinates, both corners included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkRange {
+    min: (u8, u8),
+    max: (u8, u8),
+}
+
+impl ChunkRange {
+    /// Every chunk in a region
+    pub const ALL: ChunkRange = ChunkRange {
+        min: (0, 0),
+        max: (31, 31),
+    };
+
+    /// The chunks from `(min_x, min_z)` up to and including `(max_x, max_z)`.  Returns `None` when
+    /// a corner lies outside of the region or the minimum is past the maximum.
+    pub fn new(min_x: u8, min_z: u8, max_x: u8, max_z: u8) -> Option<ChunkRange> {
+        if max_x >= 32 || max_z >= 32 || min_x > max_x || min_z > max_z {
+            return None;
+        }
+        Some(ChunkRange {
+            min: (min_x, min_z),
+            max: (max_x, max_z),
+        })
+    }
+
+    /// Whether the chunk at these coordinates lies in this range
+    pub fn contains(&self, x: u8, z: u8) -> bool {
+        (self.min.0..=self.max.0).contains(&x) && (self.min.1..=self.max.1).contains(&z)
+    }
+
+    /// The coordinates of every chunk in this range, column by column
+    pub fn coords(self) -> impl Iterator<Item = (u8, u8)> {
+        (self.min.0..=self.max.0).flat_map(move |x| (self.min.1..=self.max.1).map(move |z| (x, z)))
+    }
+}
+
+/// The order `compact` writes the chunks in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactOrder {
+    /// The order of the chunks in the header, row by row
+    Index,
+    /// Morton (Z-order) of the chunk coordinates, which keeps chunks that are close to each other
+    /// in the world close to each other in the file as well
+    Morton,
+}
+
+impl CompactOrder {
+    /// Returns the header indices of all 1024 chunks in this order
+    fn indices(self) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..1024).collect();
+        if self == CompactOrder::Morton {
+            indices.sort_by_key(|idx| {
+                let (x, z) = (idx % 32, idx / 32);
+                (0..5).fold(0, |key, bit| {
+                    key | ((x >> bit) & 1) << (2 * bit) | ((z >> bit) & 1) << (2 * bit + 1)
+                })
+            });
+        }
+        indices
+    }
+}
+
+/// Iterates over the coordinates of the chunks that exist in a region, in the order of the header.
+/// Created by `RegionFile::chunks`.
+#[derive(Debug, Clone)]
+pub struct ChunkIter<'a> {
+    offsets: &'a [u32],
+    idx: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for ChunkIter<'a> {
+    type Item = (u8, u8);
+
+    fn next(&mut self) -> Option<(u8, u8)> {
+        while self.idx < self.offsets.len() {
+            let idx = self.idx;
+            self.idx += 1;
+            if self.offsets[idx] > 0 {
+                self.remaining -= 1;
+                return Some(((idx % 32) as u8, (idx / 32) as u8));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for ChunkIter<'a> {}
+
+/// Iterates over the chunks that exist in a region along with their compression type and data, as
+/// they are stored in the region file.  Created by `RegionFile::iter_raw_chunks`.
+pub struct RawChunkIter<'a, R> {
+    region: &'a mut RegionFile<R>,
+    indices: std::vec::IntoIter<usize>,
+}
+
+impl<'a, R> Iterator for RawChunkIter<'a, R>
+where
+    R: io::Read + io::Seek,
+{
+    type Item = Result<(u8, u8, u8, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for idx in self.indices.by_ref() {
+            let (x, z) = ((idx % 32) as u8, (idx / 32) as u8);
+            if self.region.chunk_exists(x, z) {
+                let chunk = self.region.read_chunk_stored(x, z);
+                return Some(chunk.map(|(compression_type, data)| (x, z, compression_type, data)));
+            }
+        }
+        None
+    }
+}
+
+/// Which sectors of a region file are in use
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SectorUsage {
+    /// The amount of sectors in use by the header and the chunks
+    pub used_sectors: usize,
+
+    /// The bytes in the sectors allocated to chunks that aren't used by their data
+    pub wasted_bytes: usize,
+
+    /// Whether each sector of the file is in use, this covers at least the whole file
+    pub occupied: Vec<bool>,
+}
+
+/// Where a chunk is stored in a region file and what its header says about it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChunkEntry {
+    pub x: u8,
+    pub z: u8,
+
+    /// Offset of the chunk from the start of the file, in bytes
+    pub offset: u32,
+
+    /// The amount of sectors allocated to the chunk
+    pub sectors: u8,
+
+    /// Length of the chunk data including the compression type byte, as stored in front of it
+    pub data_length: usize,
+
+    /// Non-zero bytes in the allocated sectors after the chunk data
+    pub junk_bytes: usize,
+
+    pub timestamp: u32,
+
+    /// The raw compression type byte, which includes the external flag
+    pub compression_type: u8,
+}
+
+/// The size of a single chunk before and after recompressing it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChunkSizes {
+    pub x: u8,
+    pub z: u8,
+
+    /// Total length of the chunk data before recompressing
+    pub before: usize,
+
+    /// Total length of the chunk data after recompressing, the same as `before` for skipped chunks
+    pub after: usize,
+}
+
+/// Wraps the reader of a Region and keeps track of its position, so seeking to where it already is
+/// doesn't cost a call to the reader.  After an error the position is unknown until the next
+/// seek.
+struct Positioned<T> {
+    inner: T,
+    position: Option<u64>,
+}
+
+impl<T> Positioned<T> {
+    /// Moves the known position along after `res` read or wrote that many bytes
+    fn advance(&mut self, res: &io::Result<usize>) {
+        self.position = match (self.position, res) {
+            (Some(position), Ok(n)) => Some(position + *n as u64),
+            _ => None,
+        };
+    }
+}
+
+impl<T: io::Read> io::Read for Positioned<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.inner.read(buf);
+        self.advance(&res);
+        res
+    }
+}
+
+impl<T: io::Write> io::Write for Positioned<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = self.inner.write(buf);
+        self.advance(&res);
+        res
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: io::Seek> io::Seek for Positioned<T> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        match (pos, self.position) {
+            (io::SeekFrom::Start(to), Some(position)) if to == position => return Ok(position),
+            (io::SeekFrom::Current(0), Some(position)) => return Ok(position),
+            _ => {}
+        }
+
+        let res = self.inner.seek(pos);
+        self.position = res.as_ref().ok().copied();
+        res
+    }
+}
+
 /// A region file
 ///
 /// These normally have a .mca extension on disk.  They contain up to 1024 chunks, each containing
@@ -30,46 +664,124 @@ pub struct RegionFile<T> {
     /// Timestamps, indexed by chunk.  If the chunk doesn't exist, the value will be zero
     timestamps: Vec<u32>,
 
-    /// Size of each chunk, in number of 4096-byte sectors
+    /// Size of each chunk, in number of sectors
     chunk_size: Vec<u8>,
 
-    cursor: Box<T>,
+    /// The size of a sector in bytes, which the offsets and sizes in the header are counted in
+    sector_size: usize,
+
+    cursor: Box<Positioned<T>>,
+
+    /// Directory and region coordinates used for chunks stored in separate `.mcc` files
+    external: Option<(PathBuf, i32, i32)>,
 }
 
 impl<R> RegionFile<R>
 where
-    R: io::Read + io::Seek + io::Write,
+    R: io::Read + io::Seek,
 {
-    /// Parses a region file
-    pub fn new(mut r: R) -> Result<RegionFile<R>, Error> {
+    /// Parses a region file.  Files too short to hold the header give `Error::NotARegionFile`.
+    pub fn new(r: R) -> Result<RegionFile<R>, Error> {
+        RegionFile::with_sector_size(r, SECTOR_SIZE)
+    }
+
+    /// Parses a region file that uses sectors of `sector_size` bytes instead of the usual
+    /// `SECTOR_SIZE`, which has to be a power of two of at least 16 bytes.  The offset and
+    /// timestamp tables stay 4096 bytes each, the chunks start at the first sector after them.
+    pub fn with_sector_size(mut r: R, sector_size: usize) -> Result<RegionFile<R>, Error> {
+        if !sector_size.is_power_of_two() || sector_size < 16 || sector_size > u32::MAX as usize {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("sector size of {} bytes isn't supported", sector_size),
+            )));
+        }
+
+        let len = r.seek(io::SeekFrom::End(0))?;
+        if len < HEADER_LEN {
+            return Err(Error::NotARegionFile { len });
+        }
+        r.seek(io::SeekFrom::Start(0))?;
+
         let mut offsets = Vec::with_capacity(1024);
         let mut timestamps = Vec::with_capacity(1024);
         let mut chunk_size = Vec::with_capacity(1024);
 
-        for _ in 0..1024 {
-            let v = r.read_u32::<BigEndian>()?;
+        // both tables are read with a single read each, rather than one for every entry
+        let mut table = [0; TABLE_LEN as usize];
+        r.read_exact(&mut table)?;
+        for v in table.chunks_exact(4) {
+            let v = u32::from_be_bytes([v[0], v[1], v[2], v[3]]);
 
-            // upper 3 bytes are an offset
-            let offset = v >> 8;
+            // upper 3 bytes are an offset, in sectors.  Anything from 4 GiB on can't be a byte
+            // offset we're able to keep, and no region file gets anywhere near that big
+            let offset = (v >> 8).checked_mul(sector_size as u32).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("chunk offset of {} sectors is out of range", v >> 8),
+                )
+            })?;
             let sector_count = (v & 0xff) as u8;
 
-            offsets.push(offset * 4096);
+            offsets.push(offset);
             chunk_size.push(sector_count);
         }
 
-        for _ in 0..1024 {
-            let ts = r.read_u32::<BigEndian>()?;
-            timestamps.push(ts);
+        r.read_exact(&mut table)?;
+        for ts in table.chunks_exact(4) {
+            timestamps.push(u32::from_be_bytes([ts[0], ts[1], ts[2], ts[3]]));
         }
 
         Ok(RegionFile {
-            offsets: offsets,
-            timestamps: timestamps,
-            chunk_size: chunk_size,
-            cursor: Box::new(r),
+            offsets,
+            timestamps,
+            chunk_size,
+            sector_size,
+            cursor: Box::new(Positioned {
+                inner: r,
+                position: None,
+            }),
+            external: None,
         })
     }
 
+    /// Sets the directory chunks that are too large for the region file go to, as `.mcc` files
+    /// named after their absolute chunk coordinates.  This is the directory the region file is
+    /// in, with `region_x` and `region_z` as given by `parse_region_coords`.  Without it chunks
+    /// like that can't be read or written.
+    pub fn set_external_dir(&mut self, dir: PathBuf, region_x: i32, region_z: i32) {
+        self.external = Some((dir, region_x, region_z));
+    }
+
+    /// Sets the external directory to the one the region file at `path` is in, when its name
+    /// tells which region it is
+    pub fn set_region_path(&mut self, path: &Path) {
+        self.set_region_path_with_mcc_dir(path, None)
+    }
+
+    /// Same as `set_region_path`, but the `.mcc` files are kept in `mcc_dir` instead of the
+    /// directory of the region file, for setups that store them elsewhere.  `None` uses the
+    /// directory of the region file.
+    pub fn set_region_path_with_mcc_dir(&mut self, path: &Path, mcc_dir: Option<&Path>) {
+        if let (Some((x, z)), Some(dir)) = (parse_region_coords(path), path.parent()) {
+            self.set_external_dir(mcc_dir.unwrap_or(dir).to_path_buf(), x, z);
+        }
+    }
+
+    /// The path of the `.mcc` file for a chunk, if an external directory was set
+    pub fn external_chunk_path(&self, x: u8, z: u8) -> Option<PathBuf> {
+        let (dir, region_x, region_z) = self.external.as_ref()?;
+        Some(dir.join(format!(
+            "c.{}.{}.mcc",
+            region_x * 32 + x as i32,
+            region_z * 32 + z as i32
+        )))
+    }
+
+    /// Gives back the underlying reader, including any changes that were written to it
+    pub fn into_inner(self) -> R {
+        self.cursor.inner
+    }
+
     /// Returns a unix timestamp of when a given chunk was last modified.  If the chunk does not
     /// exist in this Region, return `None`.
     ///
@@ -87,6 +799,35 @@ where
         }
     }
 
+    /// Returns the timestamps of all 1024 chunks, indexed by `x + z * 32`.  Chunks that don't
+    /// exist normally have a timestamp of zero.
+    pub fn timestamps(&self) -> &[u32] {
+        &self.timestamps
+    }
+
+    /// Returns the byte-offsets of all 1024 chunks (as measured from the start of the file),
+    /// indexed by `x + z * 32`.  An offset of zero means the chunk does not exist.
+    pub fn offsets(&self) -> &[u32] {
+        &self.offsets
+    }
+
+    /// The amount of bytes the file runs on past its last whole sector.  Region files always end
+    /// on a sector boundary, so anything other than 0 means it got truncated or corrupted.
+    pub fn partial_sector_bytes(&mut self) -> Result<usize, Error> {
+        let len = self.cursor.seek(io::SeekFrom::End(0))?;
+        Ok((len % self.sector_size as u64) as usize)
+    }
+
+    /// Reads the offset and timestamp tables straight from the file, as they are on disk.  Handy
+    /// for bug reports about corrupt files, as the parsed tables leave out whatever didn't make
+    /// sense.
+    pub fn header_bytes(&mut self) -> io::Result<[u8; HEADER_LEN as usize]> {
+        let mut header = [0; HEADER_LEN as usize];
+        self.cursor.seek(io::SeekFrom::Start(0))?;
+        self.cursor.read_exact(&mut header)?;
+        Ok(header)
+    }
+
     /// Returns the byte-offset for a given chunk (as measured from the start of the file).
     ///
     /// # Panics
@@ -108,223 +849,3833 @@ where
         assert!(x < 32);
         assert!(z < 32);
         let idx = x as usize % 32 + (z as usize % 32) * 32;
-        self.chunk_size[idx] as usize * 4096
+        self.chunk_size[idx] as usize * self.sector_size
     }
 
-    /// Does the given chunk exist in the Region
+    /// The amount of sectors the offset and timestamp tables take up at the start of the file
+    fn header_sectors(&self) -> usize {
+        (HEADER_LEN as usize).div_ceil(self.sector_size)
+    }
+
+    /// The amount of sectors a chunk with a length field of `total_len` needs
+    fn sectors_for(&self, total_len: usize) -> usize {
+        (total_len + 4).div_ceil(self.sector_size)
+    }
+
+    /// Returns the amount of sectors a chunk has, as stored in the header.  This is 0 for chunks
+    /// that don't exist.
     ///
     /// # Panics
     ///
     /// x and z must be between 0 and 31 (inclusive).  If not, panics.
-    pub fn chunk_exists(&self, x: u8, z: u8) -> bool {
+    pub fn get_chunk_sectors(&self, x: u8, z: u8) -> u8 {
         assert!(x < 32);
         assert!(z < 32);
         let idx = x as usize % 32 + (z as usize % 32) * 32;
-        self.offsets.get(idx).map_or(false, |v| *v > 0)
+        self.chunk_size[idx]
     }
 
-    /// Figures out how many 'junk' bytes there are present for a specific chunk
+    /// Returns the first sector of a chunk and the amount of sectors it has, or `None` if the
+    /// chunk doesn't exist
     ///
     /// # Panics
     ///
     /// x and z must be between 0 and 31 (inclusive).  If not, panics.
-    pub fn junk_bytes(&mut self, x: u8, z: u8) -> Result<usize, Error> {
-        let offset = self.get_chunk_offset(x, z);
-        let chunk_size = self.get_chunk_size(x, z);
+    pub fn chunk_sector_range(&self, x: u8, z: u8) -> Option<(u32, u8)> {
+        if !self.chunk_exists(x, z) {
+            return None;
+        }
+        Some((
+            self.get_chunk_offset(x, z) / self.sector_size as u32,
+            self.get_chunk_sectors(x, z),
+        ))
+    }
 
-        self.cursor.seek(io::SeekFrom::Start(offset as u64))?;
-        let total_len = self.cursor.read_u32::<BigEndian>()? as usize;
-        let _ = self.cursor.read_u8()?; // this is the compression type but this is not relevant for us here
-
-        let data = {
-            // we subtract 5 here as the first 5 bytes are used for the length of the actual data
-            // and the compression mode
-            let mut v: Vec<u8> = Vec::with_capacity(chunk_size - 5);
-            v.resize(chunk_size - 5, 0);
-            self.cursor.read_exact(&mut v)?;
-            v
-        };
+    /// Does the given chunk exist in the Region.  A header entry with an offset but no sectors
+    /// still counts as a chunk, but a corrupt one: reading it gives `Error::CorruptChunk`.
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    pub fn chunk_exists(&self, x: u8, z: u8) -> bool {
+        assert!(x < 32);
+        assert!(z < 32);
+        let idx = x as usize % 32 + (z as usize % 32) * 32;
+        self.offsets.get(idx).is_some_and(|v| *v > 0)
+    }
 
-        for &n in &data[total_len..] {
-            if n != 0u8 {
-                return Ok(chunk_size - total_len);
-            }
+    /// Returns the amount of chunks that exist in the Region
+    pub fn count_chunks(&self) -> usize {
+        self.offsets.iter().filter(|v| **v > 0).count()
+    }
+
+    /// Returns the coordinates of all the chunks that exist in the Region
+    pub fn chunks(&self) -> ChunkIter<'_> {
+        ChunkIter {
+            offsets: &self.offsets,
+            idx: 0,
+            remaining: self.count_chunks(),
         }
+    }
 
-        Ok(0)
+    /// Returns the coordinates of the chunks that were modified after `ts`, going by the timestamp
+    /// table alone.  Slots without a chunk are left out, even when a timestamp was left behind.
+    pub fn chunks_modified_since(&self, ts: u32) -> Vec<(u8, u8)> {
+        self.chunks()
+            .filter(|(x, z)| self.timestamps[*x as usize + *z as usize * 32] > ts)
+            .collect()
     }
 
-    fn recompress_chunk(
-        &mut self,
-        x: u8,
-        z: u8,
-        level: flate2::Compression,
-    ) -> Result<(usize, usize), Error> {
+    /// Reads the length field and the compression type stored in front of a chunk's data.  Chunks
+    /// without any sectors don't have those, so they're corrupt.
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    fn read_chunk_header(&mut self, x: u8, z: u8) -> Result<(usize, u8), Error> {
+        if self.get_chunk_sectors(x, z) == 0 {
+            return Err(Error::CorruptChunk { x, z });
+        }
         let offset = self.get_chunk_offset(x, z);
-        let chunk_size = self.get_chunk_size(x, z);
 
+        // the length and compression type are read in one go, to keep the amount of reads down
+        let mut header = [0; 5];
         self.cursor.seek(io::SeekFrom::Start(offset as u64))?;
-        let total_len = self.cursor.read_u32::<BigEndian>()? as usize;
-        let compression_type = self.cursor.read_u8()?;
+        self.chunk_reader(x, z, 0).read_exact(&mut header)?;
+        let total_len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
 
-        assert!(chunk_size > total_len);
+        Ok((total_len, header[4]))
+    }
 
-        if compression_type != 2 {
-            return Err(Error::UnsupportedCompressionFormat { compression_type });
+    /// Reads on from the current position, which is `pos` bytes into the given chunk, up to the end
+    /// of the last sector of that chunk.  Even with a corrupt length the data of the chunks after
+    /// it can't end up being read as part of it this way.
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    fn chunk_reader(&mut self, x: u8, z: u8, pos: usize) -> io::Take<&mut Box<Positioned<R>>> {
+        let limit = self.get_chunk_size(x, z).saturating_sub(pos);
+        (&mut self.cursor).take(limit as u64)
+    }
+
+    /// Makes sure the length field of a chunk fits in the sectors it has, as it's taken straight
+    /// from the file and may well be corrupt.  A length of 0 isn't valid either, as the
+    /// compression type byte is always part of it.
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    fn check_chunk_len(&self, x: u8, z: u8, total_len: usize) -> Result<(), Error> {
+        let chunk_size = self.get_chunk_size(x, z);
+        if total_len == 0 || total_len + 4 > chunk_size {
+            return Err(Error::CorruptChunk { x, z });
         }
+        Ok(())
+    }
 
-        let compressed_data = {
-            let mut v: Vec<u8> = Vec::with_capacity(total_len - 1);
-            v.resize(total_len - 1, 0);
-            self.cursor.read_exact(&mut v)?;
-            v
+    /// Returns the compression format of a given chunk
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    pub fn get_compression_type(&mut self, x: u8, z: u8) -> Result<CompressionFormat, Error> {
+        CompressionFormat::try_from(self.read_chunk_header(x, z)?.1 & !EXTERNAL_FLAG)
+    }
+
+    /// Returns the coordinates of the chunks whose data is stored in a `.mcc` file, only the
+    /// header in front of every chunk is read
+    pub fn external_chunks(&mut self) -> Result<Vec<(u8, u8)>, Error> {
+        let mut out = Vec::new();
+        for (x, z) in self.chunks().collect::<Vec<_>>() {
+            if self.read_chunk_header(x, z)?.1 & EXTERNAL_FLAG != 0 {
+                out.push((x, z));
+            }
+        }
+        Ok(out)
+    }
+
+    /// Loads the decompressed data of a chunk, this is the raw NBT of the chunk
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    pub fn load_chunk(&mut self, x: u8, z: u8) -> Result<Vec<u8>, Error> {
+        if !self.chunk_exists(x, z) {
+            return Err(Error::MissingChunk { x, z });
+        }
+
+        let (compression_type, data) = self.read_chunk_raw(x, z)?;
+        decompress(
+            CompressionFormat::try_from(compression_type)?,
+            data.as_slice(),
+        )
+        .map_err(|error| truncated_as_corrupt(error, x, z))
+    }
+
+    /// Loads a chunk and parses it into an NBT value
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    #[cfg(feature = "nbt")]
+    pub fn load_chunk_nbt(&mut self, x: u8, z: u8) -> Result<fastnbt::Value, Error> {
+        let data = self.load_chunk(x, z)?;
+        Ok(fastnbt::from_bytes(&data)?)
+    }
+
+    /// Reads the DataVersion of a chunk, which is the version of Minecraft it was last saved by
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    #[cfg(feature = "nbt")]
+    pub fn chunk_data_version(&mut self, x: u8, z: u8) -> Result<i32, Error> {
+        match self.load_chunk_nbt(x, z)? {
+            fastnbt::Value::Compound(root) => match root.get("DataVersion") {
+                Some(fastnbt::Value::Int(version)) => Ok(*version),
+                _ => Err(Error::MissingTag {
+                    name: "DataVersion",
+                }),
+            },
+            _ => Err(Error::MissingTag {
+                name: "DataVersion",
+            }),
+        }
+    }
+
+    /// Reads the InhabitedTime of a chunk, which is the amount of ticks players have spent in it
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    #[cfg(feature = "nbt")]
+    pub fn chunk_inhabited_time(&mut self, x: u8, z: u8) -> Result<i64, Error> {
+        let root = match self.load_chunk_nbt(x, z)? {
+            fastnbt::Value::Compound(root) => root,
+            _ => {
+                return Err(Error::MissingTag {
+                    name: "InhabitedTime",
+                })
+            }
         };
 
-        // we decode the original stream and re compress it with the specified compression level
-        let mut decoder = flate2::read::ZlibDecoder::new(io::Cursor::new(compressed_data));
-        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), level);
+        // since 1.18 the tag is at the root of the chunk, before that it was inside of Level
+        let inhabited = match (root.get("InhabitedTime"), root.get("Level")) {
+            (Some(inhabited), _) => Some(inhabited),
+            (None, Some(fastnbt::Value::Compound(level))) => level.get("InhabitedTime"),
+            _ => None,
+        };
 
-        // we copy the entire decoder into the new encoder
-        io::copy(&mut decoder, &mut encoder)?;
+        match inhabited {
+            Some(fastnbt::Value::Long(ticks)) => Ok(*ticks),
+            _ => Err(Error::MissingTag {
+                name: "InhabitedTime",
+            }),
+        }
+    }
 
-        let mut compressed = encoder.finish()?;
-        let new_len = compressed.len() + 1;
+    /// Counts the block entities (chests, signs and the like) and entities (item frames, armor
+    /// stands, mobs) in a chunk.  Lists that aren't there count as empty, since 1.17 entities are
+    /// kept in the region files of the `entities` folder instead of with the rest of the chunk.
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    #[cfg(feature = "nbt")]
+    pub fn chunk_entity_counts(&mut self, x: u8, z: u8) -> Result<(usize, usize), Error> {
+        let root = match self.load_chunk_nbt(x, z)? {
+            fastnbt::Value::Compound(root) => root,
+            _ => return Err(Error::MissingTag { name: "Level" }),
+        };
 
-        // make sure the new length actually fits within the chunk size
-        assert!(chunk_size - 5 > new_len);
+        let list_len = |compound: &HashMap<String, fastnbt::Value>, name| match compound.get(name) {
+            Some(fastnbt::Value::List(list)) => list.len(),
+            _ => 0,
+        };
 
-        // pad the rest with zeros again
-        compressed.resize(chunk_size - 5, 0);
+        // since 1.18 the lists are at the root of the chunk, with the block entities renamed,
+        // before that they were both inside of Level
+        match root.get("Level") {
+            Some(fastnbt::Value::Compound(level)) => {
+                Ok((list_len(level, "TileEntities"), list_len(level, "Entities")))
+            }
+            _ => Ok((
+                list_len(&root, "block_entities"),
+                list_len(&root, "Entities"),
+            )),
+        }
+    }
 
-        // as our data is prepared by now we're moving back to the start of this chunk
-        self.cursor.seek(io::SeekFrom::Start(offset as u64))?;
+    /// Returns the coordinates of all the chunks that players have spent less than `ticks` in.
+    /// Chunks without an InhabitedTime are never included
+    #[cfg(feature = "nbt")]
+    pub fn chunks_below_inhabited(&mut self, ticks: i64) -> Result<Vec<(u8, u8)>, Error> {
+        let mut out = Vec::new();
+        let chunks: Vec<(u8, u8)> = self.chunks().collect();
+        for (x, z) in chunks {
+            match self.chunk_inhabited_time(x, z) {
+                Ok(inhabited) if inhabited < ticks => out.push((x, z)),
+                Ok(_) | Err(Error::MissingTag { .. }) => {}
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(out)
+    }
 
-        // then we right away write the new length and write the compression type (which will be the same)
-        self.cursor.write_u32::<BigEndian>(new_len as u32)?;
-        self.cursor.write_u8(compression_type)?;
+    /// Gathers some statistics about the Region
+    pub fn info(&mut self) -> Result<InfoReport, Error> {
+        let file_len = self.cursor.seek(io::SeekFrom::End(0))?;
 
-        // and afterwards we're writing the newly compressed data
-        self.cursor.write(&compressed)?;
+        let mut report = InfoReport::default();
 
-        // we should be at the end of a file chunk now
-        debug_assert_eq!(
-            self.cursor.seek(io::SeekFrom::Current(0)).unwrap() % 4096,
-            0
-        );
+        for idx in 0..1024 {
+            let (x, z) = ((idx % 32) as u8, (idx / 32) as u8);
+            if !self.chunk_exists(x, z) {
+                continue;
+            }
+
+            let (total_len, compression_type) = self.read_chunk_header(x, z)?;
+
+            report.data_bytes += total_len + 4;
+            report.allocated_bytes += self.get_chunk_size(x, z);
+            *report
+                .compression_types
+                .entry(compression_type)
+                .or_insert(0) += 1;
+
+            #[cfg(feature = "nbt")]
+            {
+                match self.chunk_data_version(x, z) {
+                    Ok(version) => *report.data_versions.entry(version).or_insert(0) += 1,
+                    Err(Error::MissingTag { .. }) => report.chunks_without_data_version += 1,
+                    Err(error) => return Err(error),
+                };
+            }
+
+            let ts = self.timestamps[idx];
+            report.oldest_timestamp = Some(report.oldest_timestamp.map_or(ts, |v| v.min(ts)));
+            report.newest_timestamp = Some(report.newest_timestamp.map_or(ts, |v| v.max(ts)));
+        }
+
+        report.chunks = self.count_chunks();
+        report.total_sectors = file_len.div_ceil(self.sector_size as u64) as usize;
+        report.used_sectors = self.sector_usage()?.used_sectors;
 
-        Ok((total_len, new_len))
+        Ok(report)
     }
 
-    pub fn recompress_region(
-        &mut self,
-        level: flate2::Compression,
-    ) -> Result<(usize, usize), Error> {
-        let mut out: (usize, usize) = (0, 0);
-        for x in 0..32 {
-            for z in 0..32 {
-                if self.chunk_exists(x, z) {
-                    let res = self.recompress_chunk(x, z, level)?;
-                    out.0 += res.0;
-                    out.1 += res.1;
+    /// Describes every chunk in the Region, in index order
+    pub fn chunk_entries(&mut self) -> Result<Vec<ChunkEntry>, Error> {
+        let chunks: Vec<(u8, u8)> = self.chunks().collect();
+        chunks
+            .into_iter()
+            .map(|(x, z)| {
+                let (data_length, compression_type) = self.read_chunk_header(x, z)?;
+                Ok(ChunkEntry {
+                    x,
+                    z,
+                    offset: self.get_chunk_offset(x, z),
+                    sectors: self.chunk_size[x as usize + z as usize * 32],
+                    data_length,
+                    junk_bytes: self.junk_bytes(x, z)?,
+                    timestamp: self.get_chunk_timestamp(x, z).unwrap_or(0),
+                    compression_type,
+                })
+            })
+            .collect()
+    }
+
+    /// Figures out which sectors are in use and how much space is wasted in the sectors allocated
+    /// to chunks, which is a good indication of how much a `compact` would save
+    pub fn sector_usage(&mut self) -> Result<SectorUsage, Error> {
+        let file_len = self.cursor.seek(io::SeekFrom::End(0))?;
+
+        // the offset and timestamp tables always take up the first sectors
+        let header_sectors = self.header_sectors();
+        let file_sectors = file_len.div_ceil(self.sector_size as u64) as usize;
+        let mut occupied = vec![false; file_sectors.max(header_sectors)];
+        for sector in &mut occupied[..header_sectors] {
+            *sector = true;
+        }
+
+        let mut wasted_bytes = 0;
+        for idx in 0..1024 {
+            let (x, z) = ((idx % 32) as u8, (idx / 32) as u8);
+            let (first, count) = match self.chunk_sector_range(x, z) {
+                Some((first, count)) => (first as usize, count as usize),
+                None => continue,
+            };
+            if count == 0 {
+                continue;
+            }
+            for sector in first..first + count {
+                if sector >= occupied.len() {
+                    occupied.resize(sector + 1, false);
                 }
+                occupied[sector] = true;
             }
+
+            let (total_len, _) = self.read_chunk_header(x, z)?;
+            wasted_bytes += (count * self.sector_size).saturating_sub(total_len + 4);
         }
-        Ok(out)
+
+        Ok(SectorUsage {
+            used_sectors: occupied.iter().filter(|v| **v).count(),
+            wasted_bytes,
+            occupied,
+        })
     }
 
-    fn clean_chunk(&mut self, x: u8, z: u8) -> Result<usize, Error> {
+    /// Figures out how many 'junk' bytes there are present for a specific chunk
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    pub fn junk_bytes(&mut self, x: u8, z: u8) -> Result<usize, Error> {
         let offset = self.get_chunk_offset(x, z);
         let chunk_size = self.get_chunk_size(x, z);
 
+        // we read the whole chunk at once, the first 4 bytes are the length of the actual data
+        // (which includes the compression type byte that follows it)
+        let mut data: Vec<u8> = vec![0; chunk_size];
         self.cursor.seek(io::SeekFrom::Start(offset as u64))?;
-        let total_len = self.cursor.read_u32::<BigEndian>()? as usize;
+        self.chunk_reader(x, z, 0).read_exact(&mut data)?;
+        let total_len = data
+            .get(..4)
+            .map_or(0, |v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]) as usize);
+        self.check_chunk_len(x, z, total_len)?;
+
+        for &n in &data[total_len + 4..] {
+            if n != 0u8 {
+                return Ok(chunk_size - total_len - 4);
+            }
+        }
+
+        Ok(0)
+    }
+
+    /// Finds the chunks whose header entry points at the same sector as an earlier chunk, which
+    /// only happens in corrupt files.  Returns the coordinates of both chunks for every clash.
+    pub fn duplicate_offsets(&self) -> Vec<((u8, u8), (u8, u8))> {
+        let mut seen = HashMap::new();
+        let mut out = Vec::new();
+        for (x, z) in self.chunks() {
+            match seen.entry(self.get_chunk_offset(x, z)) {
+                Entry::Occupied(first) => out.push((*first.get(), (x, z))),
+                Entry::Vacant(entry) => {
+                    entry.insert((x, z));
+                }
+            }
+        }
+        out
+    }
+
+    /// Finds the chunks whose sector count in the header isn't the amount of sectors their length
+    /// field needs.  Too many sectors only waste space, too few mean the chunk is corrupt.
+    pub fn sector_count_mismatches(&mut self) -> Result<Vec<(u8, u8)>, Error> {
+        let mut out = Vec::new();
+        let chunks: Vec<(u8, u8)> = self.chunks().collect();
+        for (x, z) in chunks {
+            let sectors = self.get_chunk_sectors(x, z) as usize;
+            if sectors == 0 {
+                out.push((x, z));
+                continue;
+            }
+            let (total_len, _) = self.read_chunk_header(x, z)?;
+            if self.sectors_for(total_len) != sectors {
+                out.push((x, z));
+            }
+        }
+        Ok(out)
+    }
+
+    /// Tries to decompress every chunk, returning the coordinates of the chunks that turn out to be
+    /// unreadable
+    pub fn verify(&mut self) -> Vec<(u8, u8)> {
+        self.verify_in(ChunkRange::ALL)
+    }
+
+    /// Same as `verify`, but only tries the chunks in `range`
+    pub fn verify_in(&mut self, range: ChunkRange) -> Vec<(u8, u8)> {
+        let chunks: Vec<(u8, u8)> = self
+            .chunks()
+            .filter(|(x, z)| range.contains(*x, *z))
+            .collect();
+        chunks
+            .into_iter()
+            .filter(|(x, z)| self.load_chunk(*x, *z).is_err())
+            .collect()
+    }
+
+    /// Hashes the decompressed data of every chunk with SHA-256, in the order of their index,
+    /// along with their coordinates.  This stays the same whichever way the chunks are compressed
+    /// or laid out in the file, so it can be compared before and after recompressing to make sure
+    /// no data was lost.
+    pub fn content_digest(&mut self) -> Result<[u8; 32], Error> {
+        let mut hasher = Sha256::new();
+        let chunks: Vec<(u8, u8)> = self.chunks().collect();
+        for (x, z) in chunks {
+            let data = self.load_chunk(x, z)?;
+            hasher.update([x, z]);
+            hasher.update((data.len() as u64).to_be_bytes());
+            hasher.update(&data);
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// Returns the coordinates and amount of junk bytes of every chunk that contains junk
+    #[allow(clippy::type_complexity)]
+    pub fn junk_map(&mut self) -> Result<Vec<((u8, u8), usize)>, Error> {
+        let mut out = Vec::new();
+        let chunks: Vec<(u8, u8)> = self.chunks().collect();
+        for (x, z) in chunks {
+            let junk = self.junk_bytes(x, z)?;
+            if junk > 0 {
+                out.push(((x, z), junk));
+            }
+        }
+        Ok(out)
+    }
 
-        assert!(chunk_size > total_len);
+    /// Reads the raw compression type byte and the (still compressed) data of a chunk, for chunks
+    /// stored in a `.mcc` file this is the data from that file, without the external flag
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    fn read_chunk_raw(&mut self, x: u8, z: u8) -> Result<(u8, Vec<u8>), Error> {
+        let (compression_type, data) = self.read_chunk_stored(x, z)?;
+        if compression_type & EXTERNAL_FLAG == 0 {
+            return Ok((compression_type, data));
+        }
 
-        let size = chunk_size - total_len - 4 as usize;
+        let path = self
+            .external_chunk_path(x, z)
+            .ok_or(Error::ExternalChunk { x, z })?;
+        Ok((compression_type & !EXTERNAL_FLAG, std::fs::read(path)?))
+    }
 
-        self.cursor.seek(io::SeekFrom::Current(total_len as i64))?;
+    /// Reads the compression type byte and data of a chunk as they are stored in the region file
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    fn read_chunk_stored(&mut self, x: u8, z: u8) -> Result<(u8, Vec<u8>), Error> {
+        let (total_len, compression_type) = self.read_chunk_header(x, z)?;
+        self.check_chunk_len(x, z, total_len)?;
 
-        let zero = {
-            let mut v: Vec<u8> = Vec::with_capacity(size);
-            v.resize(size, 0);
+        let compressed_data = {
+            let mut v: Vec<u8> = vec![0; total_len - 1];
+            self.chunk_reader(x, z, 5).read_exact(&mut v)?;
             v
         };
 
-        self.cursor.write(&zero)?;
+        Ok((compression_type, compressed_data))
+    }
+
+    /// Reads all the sectors of a chunk as they are, the ones past the end of the file are left out
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    fn read_chunk_sectors(&mut self, x: u8, z: u8) -> Result<Vec<u8>, Error> {
+        let offset = self.get_chunk_offset(x, z) as u64;
+        self.cursor.seek(io::SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        self.chunk_reader(x, z, 0).read_to_end(&mut data)?;
+        Ok(data)
+    }
 
-        // we should be at the end of a file chunk now
-        debug_assert_eq!(
-            self.cursor.seek(io::SeekFrom::Current(0)).unwrap() % 4096,
-            0
-        );
+    /// Returns an iterator over the coordinates, compression type and compressed data of every
+    /// chunk in this region, in the order of the header.  The data isn't decompressed, which makes
+    /// this the way to copy chunks over to another region as they are.  For chunks stored in a
+    /// `.mcc` file this is the stub in the region file, with the external flag set in the
+    /// compression type.
+    pub fn iter_raw_chunks(&mut self) -> RawChunkIter<'_, R> {
+        self.raw_chunks_in(CompactOrder::Index)
+    }
 
-        Ok(size)
+    fn raw_chunks_in(&mut self, order: CompactOrder) -> RawChunkIter<'_, R> {
+        RawChunkIter {
+            region: self,
+            indices: order.indices().into_iter(),
+        }
     }
 
-    pub fn clean_junk(&mut self) -> Result<usize, Error> {
-        let mut out: usize = 0;
-        for x in 0..32 {
-            for z in 0..32 {
-                if self.chunk_exists(x, z) {
-                    let res = self.clean_chunk(x, z)?;
-                    out += res;
+    /// Writes a compacted copy of this region to `out`, with all the chunks packed right after each
+    /// other in the given order.  When recompress options are given every chunk gets recompressed
+    /// with them along the way, otherwise the chunks are copied as is.  External chunks small
+    /// enough to fit are moved into `out`, their `.mcc` files are left for the caller to remove
+    /// once `out` replaced this region.  The statistics also tell how many sectors this region
+    /// took up and how many `out` does.
+    ///
+    /// Chunks that can't be read, or whose `.mcc` file can't be, are copied over as they are and
+    /// counted as failed when the chunks are copied as is or `continue_on_error` is set, otherwise
+    /// they make the whole compaction fail.
+    pub fn compact<W>(
+        &mut self,
+        out: &mut W,
+        options: Option<&RecompressOptions>,
+        order: CompactOrder,
+    ) -> Result<RecompressStats, Error>
+    where
+        W: io::Write + io::Seek,
+    {
+        let mut out_stats = RecompressStats::default();
+        let mut offsets = vec![0u32; 1024];
+        let mut timestamps = self.timestamps.clone();
+        let mut scratch = Vec::new();
+
+        // the chunks start right after the offset and timestamp tables, which we write last
+        let mut sector = self.header_sectors() as u32;
+        out.seek(io::SeekFrom::Start(sector as u64 * self.sector_size as u64))?;
+
+        let sector_size = self.sector_size;
+        let keep_going = options.is_none_or(|options| options.continue_on_error);
+        for idx in order.indices() {
+            let (x, z) = ((idx % 32) as u8, (idx / 32) as u8);
+            if !self.chunk_exists(x, z) {
+                continue;
+            }
+            let region = &mut *self;
+
+            let (mut compression_type, mut data) = match region.read_chunk_stored(x, z) {
+                Ok(chunk) => chunk,
+                Err(_) if keep_going => {
+                    // there's no telling what is in the sectors of the chunk, so they're copied
+                    // over untouched.  A chunk without any sectors has nothing to copy.
+                    let raw = region.read_chunk_sectors(x, z)?;
+                    out_stats += RecompressStats::failed(x, z, 0);
+                    let sectors = raw.len().div_ceil(sector_size);
+                    if sectors > 0 {
+                        out.write_all(&raw)?;
+                        out.write_all(&vec![0; sectors * sector_size - raw.len()])?;
+                        offsets[idx] = sector << 8 | sectors as u32;
+                        sector += sectors as u32;
+                    }
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+
+            // chunks stored in a `.mcc` file are pulled back in when they fit again, otherwise
+            // they keep their stub which still points at that file
+            let mut failed = false;
+            let mut external = compression_type & EXTERNAL_FLAG != 0;
+            if external && region.external.is_some() {
+                match region.read_chunk_raw(x, z) {
+                    Ok((internal_type, internal_data)) => {
+                        if region.sectors_for(internal_data.len() + 1) <= 255 {
+                            compression_type = internal_type;
+                            data = internal_data;
+                            external = false;
+                        }
+                    }
+                    Err(_) if keep_going => failed = true,
+                    Err(error) => return Err(error),
+                }
+            }
+            let total_len = data.len() + 1;
+
+            let recompressed = match options {
+                Some(options) if !external && options.includes(x, z, region.timestamps[idx]) => {
+                    let res = CompressionFormat::try_from(compression_type)
+                        .and_then(|format| options.apply(format, &data, &mut scratch));
+                    match res {
+                        Ok(recompressed) => recompressed,
+                        Err(_) if options.continue_on_error => {
+                            failed = true;
+                            None
+                        }
+                        Err(error) => return Err(truncated_as_corrupt(error, x, z)),
+                    }
+                }
+                _ => None,
+            };
+            let (compression_type, data) = match recompressed {
+                Some(format) => {
+                    out_stats += RecompressStats::recompressed(total_len, scratch.len() + 1);
+                    if options.is_some_and(|options| options.touch) {
+                        timestamps[idx] = unix_now();
+                    }
+                    (format.to_byte(), &scratch[..])
                 }
+                None if failed => {
+                    out_stats += RecompressStats::failed(x, z, total_len);
+                    (compression_type, &data[..])
+                }
+                None => {
+                    out_stats += RecompressStats::skipped(total_len);
+                    (compression_type, &data[..])
+                }
+            };
+
+            let new_len = data.len() + 1;
+            let sectors = region.sectors_for(new_len);
+
+            // the sector count has to fit in the single byte the header has for it
+            if sectors > 255 {
+                return Err(Error::ChunkTooLarge { sectors });
             }
+
+            out.write_u32::<BigEndian>(new_len as u32)?;
+            out.write_u8(compression_type)?;
+            out.write_all(data)?;
+
+            // pad the chunk with zeros up to the next sector
+            out.write_all(&vec![0; sectors * sector_size - new_len - 4])?;
+
+            offsets[idx] = sector << 8 | sectors as u32;
+            sector += sectors as u32;
         }
-        Ok(out)
+
+        // nothing points at the chunks in `out` before the header is written, so it's discarded
+        // as a whole
+        if options.is_some_and(|options| options.discard_if_grown)
+            && out_stats.bytes_after > out_stats.bytes_before
+        {
+            return Err(Error::RegionGrew {
+                bytes_before: out_stats.bytes_before,
+                bytes_after: out_stats.bytes_after,
+            });
+        }
+
+        let mut header: Vec<u8> = Vec::with_capacity(HEADER_LEN as usize);
+        for v in offsets.iter().chain(timestamps.iter()) {
+            header.write_u32::<BigEndian>(*v)?;
+        }
+
+        out.seek(io::SeekFrom::Start(0))?;
+        out.write_all(&header)?;
+
+        let file_len = self.cursor.seek(io::SeekFrom::End(0))?;
+        out_stats.sectors_before = file_len.div_ceil(self.sector_size as u64) as usize;
+        out_stats.sectors_after = sector as usize;
+
+        Ok(out_stats)
     }
 }
-/// Loads a chunk into a parsed NBT Tag structure.
-///
-/// # Panics
-///
-/// x and z must be between 0 and 31 (inclusive).  If not, panics.
-/*pub fn load_chunk(&mut self, x: u8, z: u8) -> Result<nbt::Tag, nbt_error::Error> {
-    let offset = self.get_chunk_offset(x, z); // might panic
 
-    self.cursor.seek(SeekFrom::Start(offset as u64))?;
-    let total_len = self.cursor.read_u32::<BigEndian>()? as usize;
-    let compression_type = self.cursor.read_u8()?;
+impl<R> RegionFile<R>
+where
+    R: io::Read + io::Seek + io::Write,
+{
+    /// Recompresses a single chunk with `level`, in the compression format it is already in.
+    /// Returns `Error::MissingChunk` when there is no chunk at these coordinates, including when
+    /// they lie outside of the region.
+    pub fn recompress_chunk(
+        &mut self,
+        x: u8,
+        z: u8,
+        level: flate2::Compression,
+    ) -> Result<RecompressStats, Error> {
+        let options = RecompressOptions::new(level);
+        self.recompress_chunk_with(x, z, &options, &options.levels(), &mut Vec::new())
+    }
+
+    #[cfg(test)]
+    fn recompress_chunk_options(
+        &mut self,
+        x: u8,
+        z: u8,
+        options: &RecompressOptions,
+    ) -> Result<RecompressStats, Error> {
+        self.recompress_chunk_with(x, z, options, &options.levels(), &mut Vec::new())
+    }
 
-    if compression_type != 2 {
-        return Err(nbt_error::Error::UnsupportedCompressionFormat{compression_type});
+    /// Recompresses a chunk with each of the given levels and writes back the smallest result, the
+    /// level in `options` is ignored in favor of `levels`.
+    pub fn recompress_chunk_adaptive(
+        &mut self,
+        x: u8,
+        z: u8,
+        options: &RecompressOptions,
+        levels: &[flate2::Compression],
+    ) -> Result<RecompressStats, Error> {
+        self.recompress_chunk_with(x, z, options, levels, &mut Vec::new())
     }
 
-    let compressed_data = {
-        let mut v: Vec<u8> = Vec::with_capacity(total_len - 1);
-        v.resize(total_len - 1, 0);
-        self.cursor.read_exact(&mut v)?;
-        v
-    };
+    /// Does the actual work for `recompress_chunk_adaptive`, with `scratch` as the buffer the
+    /// chunk gets recompressed into.  Passing the same buffer for every chunk saves allocations.
+    fn recompress_chunk_with(
+        &mut self,
+        x: u8,
+        z: u8,
+        options: &RecompressOptions,
+        levels: &[flate2::Compression],
+        scratch: &mut Vec<u8>,
+    ) -> Result<RecompressStats, Error> {
+        if x >= 32 || z >= 32 || !self.chunk_exists(x, z) {
+            return Err(Error::MissingChunk { x, z });
+        }
+        if self.get_chunk_sectors(x, z) == 0 && options.continue_on_error {
+            return Ok(RecompressStats::failed(x, z, 0));
+        }
+        if !options.includes(x, z, self.get_chunk_timestamp(x, z).unwrap_or(0)) {
+            let (total_len, _) = self.read_chunk_header(x, z)?;
+            return Ok(RecompressStats::skipped(total_len));
+        }
 
-    let mut decoder = flate2::read::ZlibDecoder::new(Cursor::new(compressed_data));
+        let offset = self.get_chunk_offset(x, z);
+        let chunk_size = self.get_chunk_size(x, z);
 
-    let (_, tag) = nbt::Tag::parse(&mut decoder).unwrap();
-    Ok(tag)
+        // chunks in a `.mcc` file are left alone, recompressing them in place can't make them fit in
+        // the region file anyway
+        let (total_len, compression_type) =
+            match self
+                .read_chunk_header(x, z)
+                .and_then(|(total_len, compression_type)| {
+                    self.check_chunk_len(x, z, total_len)?;
+                    Ok((total_len, compression_type))
+                }) {
+                Ok(header) => header,
+                Err(Error::CorruptChunk { .. }) if options.continue_on_error => {
+                    return Ok(RecompressStats::failed(x, z, 0))
+                }
+                Err(error) => return Err(error),
+            };
+        if compression_type & EXTERNAL_FLAG != 0 {
+            return Ok(RecompressStats::skipped(total_len));
+        }
+        let format = CompressionFormat::try_from(compression_type)?;
 
-}*/
+        // the compressed data is decoded straight from the file, so only the recompressed copy of
+        // it is ever held in memory.  Its length was checked to stay within the chunk's sectors.
+        let format = match options.apply_levels(
+            levels,
+            format,
+            &mut self.cursor,
+            offset as u64 + 5,
+            total_len - 1,
+            scratch,
+        ) {
+            Ok(Some(format)) => format,
+            Ok(None) => return Ok(RecompressStats::skipped(total_len)),
+            Err(_) if options.continue_on_error => {
+                return Ok(RecompressStats::failed(x, z, total_len))
+            }
+            Err(error) => return Err(truncated_as_corrupt(error, x, z)),
+        };
+        let new_len = scratch.len() + 1;
 
-#[test]
-fn test_region() {
-    use std::fs::File;
+        // chunks that grew past the sectors they have are left as they were, moving them around is
+        // up to `compact`
+        if new_len + 4 > chunk_size {
+            return Ok(RecompressStats::skipped(total_len));
+        }
 
-    let f = File::open("tests/data/r.0.0.mca").unwrap();
-    let mut region = RegionFile::new(f).unwrap();
+        // pad the rest with zeros again, a chunk that fills its sectors exactly needs none
+        scratch.resize(chunk_size - 5, 0);
 
-    let ts = region.get_chunk_timestamp(0, 0).unwrap();
-    assert_eq!(ts, 1383443712);
+        // as our data is prepared by now we're moving back to the start of this chunk
+        self.cursor.seek(io::SeekFrom::Start(offset as u64))?;
 
-    let ts = region.get_chunk_timestamp(13, 23).unwrap();
-    assert_eq!(ts, 0);
+        // then we right away write the new length and the compression type
+        self.cursor.write_u32::<BigEndian>(new_len as u32)?;
+        self.cursor.write_u8(format.to_byte())?;
 
-    let ts = region.get_chunk_timestamp(14, 10).unwrap();
-    assert_eq!(ts, 1383443713);
+        // and afterwards we're writing the newly compressed data
+        self.cursor.write_all(scratch)?;
 
-    assert!(region.chunk_exists(14, 10));
-    assert!(!region.chunk_exists(15, 15));
+        // we should be at the end of a file chunk now
+        self.check_chunk_end(offset as u64 + chunk_size as u64)?;
 
-    assert_eq!(region.get_chunk_offset(0, 0), 180224);
+        if options.touch {
+            self.set_chunk_timestamp(x, z, unix_now())?;
+        }
 
-    assert_eq!(region.junk_bytes(14, 10).unwrap(), 0);
+        Ok(RecompressStats::recompressed(total_len, new_len))
+    }
+
+    pub fn recompress_region(
+        &mut self,
+        options: &RecompressOptions,
+    ) -> Result<RecompressStats, Error> {
+        self.recompress_region_with_progress(options, |_, _, _, _| {})
+    }
+
+    /// Same as `recompress_region`, but also returns the size of every chunk before and after
+    /// recompressing it, to find the chunks that compress poorly for example.
+    pub fn recompress_region_with_sizes(
+        &mut self,
+        options: &RecompressOptions,
+    ) -> Result<(RecompressStats, Vec<ChunkSizes>), Error> {
+        let mut sizes = Vec::with_capacity(self.count_chunks());
+        let stats = self.recompress_region_with_progress(options, |x, z, before, after| {
+            sizes.push(ChunkSizes {
+                x,
+                z,
+                before,
+                after,
+            })
+        })?;
+        Ok((stats, sizes))
+    }
+
+    /// Same as `recompress_region`, but afterwards also lowers the sector count of every chunk that
+    /// needs fewer sectors than it has, and zeroes the sectors it gave up.  Only the chunks the
+    /// options include are shrunk.  No chunk is moved, so
+    /// the freed sectors stay in the file until it's compacted.  Returns the amount of sectors that
+    /// were freed along with the statistics.
+    pub fn recompress_region_shrink(
+        &mut self,
+        options: &RecompressOptions,
+    ) -> Result<(RecompressStats, usize), Error> {
+        let stats = self.recompress_region(options)?;
+
+        // the chunks the options leave out are left alone entirely
+        let chunks: Vec<(u8, u8)> = self
+            .chunks()
+            .filter(|(x, z)| {
+                options.includes(*x, *z, self.get_chunk_timestamp(*x, *z).unwrap_or(0))
+            })
+            .collect();
+        let mut freed = 0;
+        for (x, z) in chunks {
+            freed += self.shrink_chunk(x, z)?;
+        }
+        Ok((stats, freed))
+    }
+
+    /// Gives up the trailing sectors of a chunk that its length field doesn't need, returns how
+    /// many that were
+    fn shrink_chunk(&mut self, x: u8, z: u8) -> Result<usize, Error> {
+        let sectors = self.get_chunk_sectors(x, z) as usize;
+        if sectors == 0 {
+            return Ok(0);
+        }
+        let (total_len, _) = self.read_chunk_header(x, z)?;
+        let needed = self.sectors_for(total_len);
+        if total_len == 0 || needed >= sectors {
+            return Ok(0);
+        }
+
+        let start = self.get_chunk_offset(x, z) as u64 + (needed * self.sector_size) as u64;
+        self.cursor.seek(io::SeekFrom::Start(start))?;
+        self.cursor
+            .write_all(&vec![0; (sectors - needed) * self.sector_size])?;
+
+        let idx = x as usize + z as usize * 32;
+        self.chunk_size[idx] = needed as u8;
+        self.write_header_entry(idx)?;
+        Ok(sectors - needed)
+    }
+
+    /// Same as `recompress_region`, but calls `cb` after every chunk with its coordinates and its
+    /// size before and after recompressing.
+    pub fn recompress_region_with_progress<F>(
+        &mut self,
+        options: &RecompressOptions,
+        cb: F,
+    ) -> Result<RecompressStats, Error>
+    where
+        F: FnMut(u8, u8, usize, usize),
+    {
+        self.recompress_region_filtered(options, |_, _, _| Ok(true), cb)
+    }
+
+    /// Same as `recompress_region_with_progress`, but only recompresses the chunks `filter` returns
+    /// true for, the others are counted as skipped.  The filter gets the region itself to look at
+    /// the chunk, which makes it possible to only recompress the chunks with junk in them using
+    /// `junk_bytes` for example.
+    pub fn recompress_region_filtered<P, F>(
+        &mut self,
+        options: &RecompressOptions,
+        mut filter: P,
+        mut cb: F,
+    ) -> Result<RecompressStats, Error>
+    where
+        P: FnMut(&mut Self, u8, u8) -> Result<bool, Error>,
+        F: FnMut(u8, u8, usize, usize),
+    {
+        let mut out = RecompressStats::default();
+        if self.count_chunks() == 0 {
+            return Ok(out);
+        }
+        if let Some(&(first, second)) = self.duplicate_offsets().first() {
+            return Err(Error::DuplicateOffset { first, second });
+        }
+
+        let levels = options.levels();
+        let mut scratch = Vec::new();
+        let mut originals = Vec::new();
+        for (x, z) in options.chunk_range.unwrap_or(ChunkRange::ALL).coords() {
+            if self.chunk_exists(x, z) {
+                let stats = if filter(self, x, z)? {
+                    if options.discard_if_grown {
+                        originals.push(self.chunk_snapshot(x, z)?);
+                    }
+                    self.recompress_chunk_with(x, z, options, &levels, &mut scratch)?
+                } else {
+                    RecompressStats::skipped(self.read_chunk_header(x, z)?.0)
+                };
+                cb(x, z, stats.bytes_before, stats.bytes_after);
+                out += stats;
+            }
+        }
+
+        if options.discard_if_grown && out.bytes_after > out.bytes_before {
+            for (x, z, data, timestamp) in originals {
+                let offset = self.get_chunk_offset(x, z);
+                self.cursor.seek(io::SeekFrom::Start(offset as u64))?;
+                self.cursor.write_all(&data)?;
+                if self.get_chunk_timestamp(x, z) != Some(timestamp) {
+                    self.set_chunk_timestamp(x, z, timestamp)?;
+                }
+            }
+            return Err(Error::RegionGrew {
+                bytes_before: out.bytes_before,
+                bytes_after: out.bytes_after,
+            });
+        }
+        Ok(out)
+    }
+
+    /// Reads every sector of a chunk as it is along with its timestamp, so it can be put back
+    /// exactly like that
+    fn chunk_snapshot(&mut self, x: u8, z: u8) -> Result<(u8, u8, Vec<u8>, u32), Error> {
+        let mut data = vec![0; self.get_chunk_size(x, z)];
+        self.cursor
+            .seek(io::SeekFrom::Start(self.get_chunk_offset(x, z) as u64))?;
+        self.cursor.read_exact(&mut data)?;
+        Ok((x, z, data, self.get_chunk_timestamp(x, z).unwrap_or(0)))
+    }
+
+    /// Writes the offset and sector count of a chunk to the header on disk
+    fn write_header_entry(&mut self, idx: usize) -> Result<(), Error> {
+        let sector = self.offsets[idx] / self.sector_size as u32;
+
+        self.cursor.seek(io::SeekFrom::Start(idx as u64 * 4))?;
+        self.cursor
+            .write_u32::<BigEndian>(sector << 8 | self.chunk_size[idx] as u32)?;
+
+        Ok(())
+    }
+
+    /// Sets the unix timestamp of when a given chunk was last modified, both in memory and in the
+    /// timestamp table on disk
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    pub fn set_chunk_timestamp(&mut self, x: u8, z: u8, ts: u32) -> Result<(), Error> {
+        assert!(x < 32);
+        assert!(z < 32);
+        let idx = x as usize % 32 + (z as usize % 32) * 32;
+
+        self.cursor
+            .seek(io::SeekFrom::Start(TABLE_LEN + idx as u64 * 4))?;
+        self.cursor.write_u32::<BigEndian>(ts)?;
+        self.timestamps[idx] = ts;
+
+        Ok(())
+    }
+
+    /// Finds room for `sectors` sectors for the chunk at `idx`, ignoring the space it currently
+    /// occupies.  Gaps between the other chunks are reused, otherwise the sectors are appended to
+    /// the end of the file
+    fn allocate_sectors(&mut self, idx: usize, sectors: usize) -> Result<u32, Error> {
+        let file_len = self.cursor.seek(io::SeekFrom::End(0))?;
+
+        let header_sectors = self.header_sectors();
+        let file_sectors = file_len.div_ceil(self.sector_size as u64) as usize;
+        let mut used = vec![false; file_sectors.max(header_sectors)];
+        for sector in &mut used[..header_sectors] {
+            *sector = true;
+        }
+        for (i, offset) in self.offsets.iter().enumerate() {
+            if i == idx || *offset == 0 {
+                continue;
+            }
+            let first = *offset as usize / self.sector_size;
+            let last = first + self.chunk_size[i] as usize;
+            if last > used.len() {
+                used.resize(last, false);
+            }
+            for sector in &mut used[first..last] {
+                *sector = true;
+            }
+        }
+
+        let mut start = 0;
+        for (sector, used) in used.iter().enumerate() {
+            if *used {
+                start = sector + 1;
+            } else if sector + 1 - start == sectors {
+                return Ok(start as u32);
+            }
+        }
+
+        // the free run at the end of the file may simply continue past it
+        Ok(start as u32)
+    }
+
+    /// Writes the NBT data of a chunk to the Region with zlib compression and `timestamp` as its
+    /// modification time.  The chunk is moved elsewhere in the file if it no longer fits in the
+    /// sectors it had, or gets added if it didn't exist yet
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    pub fn write_chunk(&mut self, x: u8, z: u8, data: &[u8], timestamp: u32) -> Result<(), Error> {
+        assert!(x < 32);
+        assert!(z < 32);
+        let idx = x as usize % 32 + (z as usize % 32) * 32;
+
+        let compressed = compress(
+            CompressionFormat::Zlib,
+            data,
+            flate2::Compression::default(),
+        )?;
+        self.write_compressed(idx, CompressionFormat::Zlib, compressed)?;
+
+        self.write_header_entry(idx)?;
+        self.set_chunk_timestamp(x, z, timestamp)
+    }
+
+    /// Writes already compressed chunk data for the chunk at `idx`, moving it elsewhere if it no
+    /// longer fits in the sectors it had.  Data that doesn't fit in 255 sectors goes to a `.mcc`
+    /// file when an external directory is set.  The header entry is left for the caller to write.
+    fn write_compressed(
+        &mut self,
+        idx: usize,
+        format: CompressionFormat,
+        mut compressed: Vec<u8>,
+    ) -> Result<(), Error> {
+        let (x, z) = ((idx % 32) as u8, (idx / 32) as u8);
+        let was_external =
+            self.chunk_size[idx] != 0 && self.read_chunk_header(x, z)?.1 & EXTERNAL_FLAG != 0;
+        let mut new_len = compressed.len() + 1;
+        let mut compression_type = format.to_byte();
+
+        let mut sectors = self.sectors_for(new_len);
+        if sectors > 255 {
+            let path = self
+                .external_chunk_path(x, z)
+                .ok_or(Error::ChunkTooLarge { sectors })?;
+            std::fs::write(path, &compressed)?;
+
+            // all that is left in the region file is the compression type with the external flag
+            compressed.clear();
+            new_len = 1;
+            compression_type |= EXTERNAL_FLAG;
+            sectors = 1;
+        }
+
+        if self.offsets[idx] == 0 || sectors > self.chunk_size[idx] as usize {
+            let sector = self.allocate_sectors(idx, sectors)?;
+            self.offsets[idx] = sector * self.sector_size as u32;
+            self.chunk_size[idx] = sectors as u8;
+        }
+
+        // pad the rest of the allocated sectors with zeros
+        compressed.resize(self.chunk_size[idx] as usize * self.sector_size - 5, 0);
+
+        self.cursor
+            .seek(io::SeekFrom::Start(self.offsets[idx] as u64))?;
+        self.cursor.write_u32::<BigEndian>(new_len as u32)?;
+        self.cursor.write_u8(compression_type)?;
+        self.cursor.write_all(&compressed)?;
+
+        // the data is back in the region file, so the `.mcc` file it was in isn't needed anymore
+        if was_external && compression_type & EXTERNAL_FLAG == 0 {
+            if let Some(path) = self.external_chunk_path(x, z) {
+                std::fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves a chunk that is stored in a `.mcc` file back into the region file, when it has
+    /// shrunk enough to fit in there again.  The `.mcc` file is removed afterwards.  Returns
+    /// whether the chunk was moved, chunks that aren't external or are still too large are left
+    /// where they are.
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    pub fn internalize_chunk(&mut self, x: u8, z: u8) -> Result<bool, Error> {
+        if !self.chunk_exists(x, z) {
+            return Err(Error::MissingChunk { x, z });
+        }
+        if self.read_chunk_header(x, z)?.1 & EXTERNAL_FLAG == 0 {
+            return Ok(false);
+        }
+
+        let (compression_type, data) = self.read_chunk_raw(x, z)?;
+        if self.sectors_for(data.len() + 1) > 255 {
+            return Ok(false);
+        }
+
+        let idx = x as usize + z as usize * 32;
+        self.write_compressed(idx, CompressionFormat::try_from(compression_type)?, data)?;
+        self.write_header_entry(idx)?;
+        Ok(true)
+    }
+
+    /// Rewrites every chunk in the Region with the `to` compression format, chunks that grow are
+    /// moved elsewhere in the file.  The level is used for the formats that have one.
+    pub fn convert_format(
+        &mut self,
+        to: CompressionFormat,
+        level: flate2::Compression,
+    ) -> Result<RecompressStats, Error> {
+        let mut out = RecompressStats::default();
+        for idx in 0..1024 {
+            let (x, z) = ((idx % 32) as u8, (idx / 32) as u8);
+            if !self.chunk_exists(x, z) {
+                continue;
+            }
+
+            let (compression_type, data) = self.read_chunk_raw(x, z)?;
+            let raw = decompress(
+                CompressionFormat::try_from(compression_type)?,
+                data.as_slice(),
+            )
+            .map_err(|error| truncated_as_corrupt(error, x, z))?;
+            let compressed = compress(to, &raw, level)?;
+            out += RecompressStats::recompressed(data.len() + 1, compressed.len() + 1);
+
+            self.write_compressed(idx, to, compressed)?;
+            self.write_header_entry(idx)?;
+        }
+        Ok(out)
+    }
+
+    /// Removes a chunk from the Region by clearing its offset and timestamp in the header, the
+    /// sectors it used are left as they are until the Region is compacted
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    pub fn delete_chunk(&mut self, x: u8, z: u8) -> Result<(), Error> {
+        assert!(x < 32);
+        assert!(z < 32);
+        let idx = x as usize % 32 + (z as usize % 32) * 32;
+
+        self.cursor.seek(io::SeekFrom::Start(idx as u64 * 4))?;
+        self.cursor.write_u32::<BigEndian>(0)?;
+        self.cursor
+            .seek(io::SeekFrom::Start(TABLE_LEN + idx as u64 * 4))?;
+        self.cursor.write_u32::<BigEndian>(0)?;
+
+        self.offsets[idx] = 0;
+        self.chunk_size[idx] = 0;
+        self.timestamps[idx] = 0;
+
+        Ok(())
+    }
+
+    /// Deletes every chunk that can't be recovered, so the rest of the region can still be loaded
+    /// by the game.  Those are the chunks whose sectors start inside of the header or run past the
+    /// end of the file, and the ones that fail to decompress.  Returns the coordinates of the
+    /// chunks that were deleted.  Chunks in a `.mcc` file are only checked when the directory of
+    /// those files was set.
+    pub fn repair(&mut self) -> Result<Vec<(u8, u8)>, Error> {
+        let file_len = self.cursor.seek(io::SeekFrom::End(0))?;
+        let chunks: Vec<(u8, u8)> = self.chunks().collect();
+
+        let mut cleared = Vec::new();
+        for (x, z) in chunks {
+            let offset = self.get_chunk_offset(x, z) as u64;
+            let end = offset + self.get_chunk_size(x, z) as u64;
+            let broken = offset < HEADER_LEN
+                || end > file_len
+                || match self.load_chunk(x, z) {
+                    Ok(_) | Err(Error::ExternalChunk { .. }) => false,
+                    Err(_) => true,
+                };
+            if broken {
+                self.delete_chunk(x, z)?;
+                cleared.push((x, z));
+            }
+        }
+        Ok(cleared)
+    }
+
+    /// Lowers the sector count in the header of every chunk that has more sectors than its length
+    /// field needs, without moving any data.  The sectors that are given up stay in the file until
+    /// it's compacted.  Chunks with too few sectors are left alone, as those are corrupt.  Returns
+    /// the coordinates of the chunks that were corrected.
+    pub fn fix_sector_counts(&mut self) -> Result<Vec<(u8, u8)>, Error> {
+        let chunks: Vec<(u8, u8)> = self.chunks().collect();
+
+        let mut fixed = Vec::new();
+        for (x, z) in chunks {
+            if self.get_chunk_sectors(x, z) == 0 {
+                continue;
+            }
+            let (total_len, _) = self.read_chunk_header(x, z)?;
+            let needed = self.sectors_for(total_len);
+            if total_len > 0 && needed < self.get_chunk_sectors(x, z) as usize {
+                let idx = x as usize + z as usize * 32;
+                self.chunk_size[idx] = needed as u8;
+                self.write_header_entry(idx)?;
+                fixed.push((x, z));
+            }
+        }
+        Ok(fixed)
+    }
+
+    /// Makes sure a chunk that was just written ended exactly at `end`, the boundary of the last
+    /// sector it has.  Anything else means the sectors of the next chunk got written to.
+    fn check_chunk_end(&mut self, end: u64) -> Result<(), Error> {
+        let position = self.cursor.stream_position()?;
+        if position != end {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "chunk was written up to byte {} instead of {}",
+                    position, end
+                ),
+            )));
+        }
+        Ok(())
+    }
+
+    fn clean_chunk(&mut self, x: u8, z: u8) -> Result<usize, Error> {
+        let offset = self.get_chunk_offset(x, z);
+        let chunk_size = self.get_chunk_size(x, z);
+
+        let (total_len, _) = self.read_chunk_header(x, z)?;
+        self.check_chunk_len(x, z, total_len)?;
+
+        let size = chunk_size - total_len - 4;
+
+        // the compression type byte was read along with the length already
+        self.cursor
+            .seek(io::SeekFrom::Current(total_len as i64 - 1))?;
+
+        let zero: Vec<u8> = vec![0; size];
+
+        self.cursor.write_all(&zero)?;
+
+        // we should be at the end of a file chunk now
+        self.check_chunk_end(offset as u64 + chunk_size as u64)?;
+
+        Ok(size)
+    }
+
+    pub fn clean_junk(&mut self) -> Result<usize, Error> {
+        self.clean_junk_modified_after(None)
+    }
+
+    /// Same as `clean_junk`, but leaves the chunks with a timestamp at or before `timestamp` as
+    /// they are
+    pub fn clean_junk_modified_after(&mut self, timestamp: Option<u32>) -> Result<usize, Error> {
+        Ok(self
+            .clean_junk_in(ChunkRange::ALL, timestamp)?
+            .bytes_cleaned)
+    }
+
+    /// Same as `clean_junk_modified_after`, but only cleans the chunks in `range` and tells how
+    /// many chunks had junk in them
+    pub fn clean_junk_in(
+        &mut self,
+        range: ChunkRange,
+        timestamp: Option<u32>,
+    ) -> Result<CleanupStats, Error> {
+        let mut stats = CleanupStats::default();
+        for (x, z) in range.coords() {
+            if !self.chunk_exists(x, z) {
+                continue;
+            }
+            let modified = self.get_chunk_timestamp(x, z).unwrap_or(0);
+            // chunks that end in nothing but zeroes are already clean and aren't written to
+            if timestamp.is_none_or(|after| modified > after) && self.junk_bytes(x, z)? > 0 {
+                stats.bytes_cleaned += self.clean_chunk(x, z)?;
+                stats.chunks_cleaned += 1;
+            } else {
+                stats.chunks_skipped += 1;
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Does what `clean_junk` does, and zeroes every other byte that isn't part of a chunk on top
+    /// of that: the sectors no chunk uses and the timestamps of chunks that don't exist.  This
+    /// makes the file compress as well as possible with an external compressor.  Returns the
+    /// amount of bytes that were overwritten.
+    pub fn zero_fill(&mut self) -> Result<usize, Error> {
+        let mut out = self.clean_junk()?;
+
+        let file_len = self.cursor.seek(io::SeekFrom::End(0))? as usize;
+        let usage = self.sector_usage()?;
+        let zero = vec![0; self.sector_size];
+        for (sector, _) in usage
+            .occupied
+            .iter()
+            .enumerate()
+            .filter(|(_, used)| !**used)
+        {
+            let start = sector * self.sector_size;
+            if start >= file_len {
+                continue;
+            }
+            let len = (file_len - start).min(self.sector_size);
+
+            self.cursor.seek(io::SeekFrom::Start(start as u64))?;
+            self.cursor.write_all(&zero[..len])?;
+            out += len;
+        }
+
+        for idx in 0..1024 {
+            if self.offsets[idx] == 0 && self.timestamps[idx] != 0 {
+                self.cursor
+                    .seek(io::SeekFrom::Start(TABLE_LEN + idx as u64 * 4))?;
+                self.cursor.write_u32::<BigEndian>(0)?;
+                self.timestamps[idx] = 0;
+                out += 4;
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+impl RegionFile<io::Cursor<Vec<u8>>> {
+    /// Parses a region file that is already in memory, use `into_inner` to get the (modified)
+    /// bytes back out
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<RegionFile<io::Cursor<Vec<u8>>>, Error> {
+        RegionFile::new(io::Cursor::new(bytes))
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl RegionFile<io::Cursor<memmap2::Mmap>> {
+    /// Opens a region file by memory mapping it, chunks are then read straight from the mapping
+    /// instead of through read calls.  The resulting Region can't be written to.
+    pub fn open_mmap(path: &Path) -> Result<RegionFile<io::Cursor<memmap2::Mmap>>, Error> {
+        let f = std::fs::File::open(path)?;
+
+        // the mapping is only sound as long as nobody truncates the file while we're using it,
+        // which is the same thing we're assuming for the regular reader
+        let map = unsafe { memmap2::Mmap::map(&f)? };
+        let mut region = RegionFile::new(io::Cursor::new(map))?;
+        region.set_region_path(path);
+        Ok(region)
+    }
+
+    /// Borrows the (still compressed) data of a chunk straight from the mapping, without the
+    /// length and compression type in front of it.  Gives `None` for missing and corrupt chunks,
+    /// and for chunks stored in a `.mcc` file as their data isn't part of the mapping.
+    ///
+    /// # Panics
+    ///
+    /// x and z must be between 0 and 31 (inclusive).  If not, panics.
+    pub fn chunk_slice(&self, x: u8, z: u8) -> Option<&[u8]> {
+        if !self.chunk_exists(x, z) {
+            return None;
+        }
+        let offset = self.get_chunk_offset(x, z) as usize;
+        let chunk_size = self.get_chunk_size(x, z);
+        let map: &[u8] = self.cursor.inner.get_ref();
+
+        let header = map.get(offset..offset + 5)?;
+        let total_len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        if total_len == 0 || total_len + 4 > chunk_size || header[4] & EXTERNAL_FLAG != 0 {
+            return None;
+        }
+        map.get(offset + 5..offset + 4 + total_len)
+    }
+}
+
+/// Extracts the coordinates of a region from its filename, region files are named `r.X.Z.mca`
+/// (or `r.X.Z.mcr` for the older McRegion format) where X and Z are the position of the region in
+/// the world
+pub fn parse_region_coords(path: &Path) -> Option<(i32, i32)> {
+    let name = path.file_name()?.to_str()?;
+    let mut parts = name.split('.');
+
+    if parts.next()? != "r" {
+        return None;
+    }
+    let x = parts.next()?.parse().ok()?;
+    let z = parts.next()?.parse().ok()?;
+    if !matches!(parts.next()?, "mca" | "mcr") || parts.next().is_some() {
+        return None;
+    }
+
+    Some((x, z))
+}
+
+/// The current time as a unix timestamp, like the ones in the timestamp table
+fn unix_now() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32)
+}
+
+/// Decoders fail with `UnexpectedEof` on streams that end before they are complete, which means
+/// the data of the chunk was cut off, at a sector boundary for example.  That's turned into a
+/// `CorruptChunk`, so it can't be mistaken for a problem with the file as a whole.
+fn truncated_as_corrupt(error: Error, x: u8, z: u8) -> Error {
+    match error {
+        Error::Io(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+            Error::CorruptChunk { x, z }
+        }
+        error => error,
+    }
+}
+
+/// Reads the `len` bytes at `start` in `source` without buffering them
+fn payload<S: io::Read + io::Seek>(
+    source: &mut S,
+    start: u64,
+    len: usize,
+) -> Result<io::Take<&mut S>, Error> {
+    source.seek(io::SeekFrom::Start(start))?;
+    Ok(source.take(len as u64))
+}
+
+/// Decompresses chunk data stored with the given compression format
+fn decompress<R: io::Read>(format: CompressionFormat, mut data: R) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::new();
+    match format {
+        CompressionFormat::Gzip => {
+            io::Read::read_to_end(&mut flate2::read::GzDecoder::new(data), &mut out)?
+        }
+        CompressionFormat::Zlib => {
+            io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(data), &mut out)?
+        }
+        CompressionFormat::Uncompressed => io::Read::read_to_end(&mut data, &mut out)?,
+        #[cfg(feature = "zstd")]
+        CompressionFormat::Zstd => io::Read::read_to_end(&mut zstd::Decoder::new(data)?, &mut out)?,
+        #[cfg(not(feature = "zstd"))]
+        CompressionFormat::Zstd => {
+            return Err(Error::UnsupportedCompressionFormat {
+                compression_type: format.to_byte(),
+            })
+        }
+    };
+    Ok(out)
+}
+
+/// Compresses raw chunk data with the given format
+fn compress(
+    format: CompressionFormat,
+    data: &[u8],
+    level: flate2::Compression,
+) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::new();
+    encode(format, data, level, &mut out)?;
+    Ok(out)
+}
+
+/// Decodes chunk data stored as `from` and encodes it again as `to` with the specified compression
+/// level into `out`, replacing whatever it contained before
+fn recompress<R: io::Read>(
+    from: CompressionFormat,
+    to: CompressionFormat,
+    data: R,
+    level: flate2::Compression,
+    out: &mut Vec<u8>,
+) -> Result<(), Error> {
+    out.clear();
+
+    // we copy the entire decoder into the new encoder
+    match from {
+        CompressionFormat::Gzip => encode(to, flate2::read::GzDecoder::new(data), level, out),
+        CompressionFormat::Zlib => encode(to, flate2::read::ZlibDecoder::new(data), level, out),
+        CompressionFormat::Uncompressed => encode(to, data, level, out),
+        #[cfg(feature = "zstd")]
+        CompressionFormat::Zstd => encode(to, zstd::Decoder::new(data)?, level, out),
+        #[cfg(not(feature = "zstd"))]
+        CompressionFormat::Zstd => Err(Error::UnsupportedCompressionFormat {
+            compression_type: from.to_byte(),
+        }),
+    }
+}
+
+/// Compresses everything `raw` reads with the given format, appending it to `out`
+fn encode<R: io::Read>(
+    format: CompressionFormat,
+    mut raw: R,
+    level: flate2::Compression,
+    out: &mut Vec<u8>,
+) -> Result<(), Error> {
+    match format {
+        CompressionFormat::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(out, level);
+            io::copy(&mut raw, &mut encoder)?;
+            encoder.finish()?;
+        }
+        CompressionFormat::Zlib => {
+            let mut encoder = flate2::write::ZlibEncoder::new(out, level);
+            io::copy(&mut raw, &mut encoder)?;
+            encoder.finish()?;
+        }
+        CompressionFormat::Uncompressed => {
+            io::copy(&mut raw, out)?;
+        }
+        #[cfg(feature = "zstd")]
+        CompressionFormat::Zstd => zstd::stream::copy_encode(raw, out, level.level() as i32)?,
+        #[cfg(not(feature = "zstd"))]
+        CompressionFormat::Zstd => {
+            return Err(Error::UnsupportedCompressionFormat {
+                compression_type: format.to_byte(),
+            })
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_region() {
+    use std::fs::File;
+
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(f).unwrap();
+
+    let ts = region.get_chunk_timestamp(0, 0).unwrap();
+    assert_eq!(ts, 1383443712);
+
+    let ts = region.get_chunk_timestamp(13, 23).unwrap();
+    assert_eq!(ts, 0);
+
+    let ts = region.get_chunk_timestamp(14, 10).unwrap();
+    assert_eq!(ts, 1383443713);
+
+    assert!(region.chunk_exists(14, 10));
+    assert!(!region.chunk_exists(15, 15));
+
+    assert_eq!(region.get_chunk_offset(0, 0), 180224);
+
+    assert_eq!(region.junk_bytes(14, 10).unwrap(), 0);
+}
+
+#[test]
+fn test_compact() {
+    use std::fs::File;
+
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(f).unwrap();
+
+    let mut out = io::Cursor::new(Vec::new());
+    let stats = region.compact(&mut out, None, CompactOrder::Index).unwrap();
+    assert_eq!(stats.bytes_before, stats.bytes_after);
+    assert_eq!(stats.chunks_skipped, 381);
+    assert_eq!(out.get_ref().len() % 4096, 0);
+
+    out.set_position(0);
+    let mut compacted = RegionFile::new(out).unwrap();
+    for x in 0..32 {
+        for z in 0..32 {
+            assert_eq!(region.chunk_exists(x, z), compacted.chunk_exists(x, z));
+            assert_eq!(
+                region.get_chunk_timestamp(x, z),
+                compacted.get_chunk_timestamp(x, z)
+            );
+            if region.chunk_exists(x, z) {
+                assert_eq!(
+                    region.read_chunk_raw(x, z).unwrap(),
+                    compacted.read_chunk_raw(x, z).unwrap()
+                );
+            }
+        }
+    }
+
+    let mut out = io::Cursor::new(Vec::new());
+    let options = RecompressOptions::new(flate2::Compression::best());
+    let stats = region
+        .compact(&mut out, Some(&options), CompactOrder::Index)
+        .unwrap();
+    assert!(stats.bytes_after <= stats.bytes_before);
+    assert_eq!(stats.chunks_recompressed, 381);
+
+    out.set_position(0);
+    let mut compacted = RegionFile::new(out).unwrap();
+    let (_, data) = compacted.read_chunk_raw(14, 10).unwrap();
+    let (_, original) = region.read_chunk_raw(14, 10).unwrap();
+    let decode = |data: &[u8]| {
+        let mut v = Vec::new();
+        io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(data), &mut v).unwrap();
+        v
+    };
+    assert_eq!(decode(&data), decode(&original));
+}
+
+#[test]
+fn test_compact_order() {
+    let f = std::fs::File::open("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(f).unwrap();
+
+    let mut files = Vec::new();
+    for order in &[CompactOrder::Index, CompactOrder::Morton] {
+        let mut out = io::Cursor::new(Vec::new());
+        region.compact(&mut out, None, *order).unwrap();
+
+        out.set_position(0);
+        let mut compacted = RegionFile::new(out).unwrap();
+        assert_eq!(compacted.count_chunks(), 381);
+        let chunks: Vec<(u8, u8)> = region.chunks().collect();
+        for (x, z) in chunks {
+            assert_eq!(
+                region.load_chunk(x, z).unwrap(),
+                compacted.load_chunk(x, z).unwrap()
+            );
+        }
+        files.push(compacted);
+    }
+
+    // (0, 1) is the 3rd chunk in Morton order, but only the 33rd in index order
+    assert!(files[0].get_chunk_offset(0, 1) > files[0].get_chunk_offset(2, 0));
+    assert!(files[1].get_chunk_offset(0, 1) < files[1].get_chunk_offset(2, 0));
+}
+
+#[test]
+fn test_min_saving() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(io::Cursor::new(data.clone())).unwrap();
+
+    let mut options = RecompressOptions::new(flate2::Compression::best());
+    options.min_saving = 4096;
+    let stats = region.recompress_chunk_options(14, 10, &options).unwrap();
+    assert_eq!(stats.chunks_skipped, 1);
+    assert_eq!(stats.bytes_before, stats.bytes_after);
+    assert_eq!(region.cursor.inner.get_ref(), &data);
+
+    options.min_saving = 0;
+    let stats = region.recompress_chunk_options(14, 10, &options).unwrap();
+    assert_eq!(stats.chunks_recompressed, 1);
+    assert_ne!(region.cursor.inner.get_ref(), &data);
+}
+
+#[test]
+fn test_recompress_twice() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    let options = RecompressOptions::new(flate2::Compression::best());
+
+    let stats = region.recompress_region(&options).unwrap();
+    assert_eq!(stats.chunks_recompressed, 381);
+
+    let once = region.into_inner().into_inner();
+    let mut region = RegionFile::from_bytes(once.clone()).unwrap();
+    let stats = region.recompress_region(&options).unwrap();
+    assert_eq!(stats.chunks_skipped, 381);
+    assert_eq!(stats.chunks_recompressed, 0);
+    assert_eq!(region.into_inner().into_inner(), once);
+}
+
+#[test]
+fn test_min_chunk_bytes() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+
+    let chunks: Vec<(u8, u8)> = region.chunks().collect();
+    let before: Vec<(u8, Vec<u8>)> = chunks
+        .iter()
+        .map(|(x, z)| region.read_chunk_raw(*x, *z).unwrap())
+        .collect();
+    let mut sizes: Vec<usize> = before.iter().map(|(_, data)| data.len()).collect();
+    sizes.sort_unstable();
+
+    // everything below the median is considered tiny
+    let mut options = RecompressOptions::new(flate2::Compression::best());
+    options.min_chunk_bytes = sizes[sizes.len() / 2];
+    let tiny = sizes
+        .iter()
+        .filter(|s| **s < options.min_chunk_bytes)
+        .count();
+    assert!(tiny > 0);
+
+    let stats = region.recompress_region(&options).unwrap();
+    assert!(stats.chunks_skipped >= tiny);
+    assert!(stats.chunks_recompressed > 0);
+    for ((x, z), (compression_type, data)) in chunks.into_iter().zip(before) {
+        if data.len() < options.min_chunk_bytes {
+            assert_eq!(
+                region.read_chunk_raw(x, z).unwrap(),
+                (compression_type, data)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_recompress_progress() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(io::Cursor::new(data)).unwrap();
+
+    let mut options = RecompressOptions::new(flate2::Compression::fast());
+    options.min_saving = 4096;
+    let mut calls = 0;
+    let stats = region
+        .recompress_region_with_progress(&options, |x, z, before, after| {
+            assert!(x < 32 && z < 32);
+            assert_eq!(before, after);
+            calls += 1;
+        })
+        .unwrap();
+    assert_eq!(calls, 381);
+    assert_eq!(stats.chunks_skipped, 381);
+}
+
+#[test]
+fn test_adaptive() {
+    use flate2::Compression;
+
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let options = RecompressOptions::new(Compression::fast());
+    let levels = [
+        Compression::new(6),
+        Compression::new(7),
+        Compression::best(),
+    ];
+
+    let mut region = RegionFile::new(io::Cursor::new(data.clone())).unwrap();
+    let adaptive = region
+        .recompress_chunk_adaptive(14, 10, &options, &levels)
+        .unwrap();
+    assert_eq!(adaptive.chunks_recompressed, 1);
+
+    for level in &levels {
+        let mut region = RegionFile::new(io::Cursor::new(data.clone())).unwrap();
+        let fixed = region.recompress_chunk(14, 10, *level).unwrap();
+        assert!(adaptive.bytes_after <= fixed.bytes_after);
+    }
+}
+
+#[test]
+fn test_touch() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut options = RecompressOptions::new(flate2::Compression::best());
+    let before = unix_now();
+
+    let mut region = RegionFile::new(io::Cursor::new(data.clone())).unwrap();
+    let original = region.get_chunk_timestamp(14, 10);
+    region.recompress_chunk_options(14, 10, &options).unwrap();
+    assert_eq!(region.get_chunk_timestamp(14, 10), original);
+
+    options.touch = true;
+    let mut region = RegionFile::new(io::Cursor::new(data.clone())).unwrap();
+    region.recompress_chunk_options(14, 10, &options).unwrap();
+    assert!(region.get_chunk_timestamp(14, 10).unwrap() >= before);
+    let region = RegionFile::new(io::Cursor::new(region.into_inner().into_inner())).unwrap();
+    assert!(region.get_chunk_timestamp(14, 10).unwrap() >= before);
+
+    // compact only touches the chunks that actually got recompressed
+    let mut region = RegionFile::new(io::Cursor::new(data)).unwrap();
+    let mut out = io::Cursor::new(Vec::new());
+    region.compact(&mut out, None, CompactOrder::Index).unwrap();
+    out.set_position(0);
+    assert_eq!(
+        RegionFile::new(out).unwrap().get_chunk_timestamp(14, 10),
+        original
+    );
+
+    let mut out = io::Cursor::new(Vec::new());
+    region
+        .compact(&mut out, Some(&options), CompactOrder::Index)
+        .unwrap();
+    out.set_position(0);
+    let compacted = RegionFile::new(out).unwrap();
+    assert!(compacted.get_chunk_timestamp(14, 10).unwrap() >= before);
+    assert_eq!(region.get_chunk_timestamp(14, 10), original);
+}
+
+#[test]
+fn test_allow_store() {
+    let noise = incompressible(3000);
+
+    let data = region_with_chunks(&[((0, 0), &noise)]);
+    let mut options = RecompressOptions::new(flate2::Compression::best());
+
+    let mut region = RegionFile::new(io::Cursor::new(data.clone())).unwrap();
+    let stats = region.recompress_chunk_options(0, 0, &options).unwrap();
+    assert!(stats.bytes_after > noise.len());
+    assert_eq!(
+        region.get_compression_type(0, 0).unwrap(),
+        CompressionFormat::Zlib
+    );
+
+    options.allow_store = true;
+    let mut region = RegionFile::new(io::Cursor::new(data)).unwrap();
+    let stats = region.recompress_chunk_options(0, 0, &options).unwrap();
+    assert_eq!(stats.bytes_after, noise.len() + 1);
+    assert!(stats.bytes_after < stats.bytes_before);
+    assert_eq!(
+        region.get_compression_type(0, 0).unwrap(),
+        CompressionFormat::Uncompressed
+    );
+    assert_eq!(region.load_chunk(0, 0).unwrap(), noise);
+}
+
+#[test]
+fn test_compression_format() {
+    for format in &[
+        CompressionFormat::Gzip,
+        CompressionFormat::Zlib,
+        CompressionFormat::Uncompressed,
+        CompressionFormat::Zstd,
+    ] {
+        assert_eq!(
+            CompressionFormat::try_from(u8::from(*format)).unwrap(),
+            *format
+        );
+    }
+    assert_eq!(CompressionFormat::Gzip.to_byte(), 1);
+    assert_eq!(CompressionFormat::Zlib.to_byte(), 2);
+    assert_eq!(CompressionFormat::Uncompressed.to_byte(), 3);
+    assert_eq!(CompressionFormat::Zstd.to_byte(), 4);
+    assert!(matches!(
+        CompressionFormat::try_from(5),
+        Err(Error::UnsupportedCompressionFormat {
+            compression_type: 5
+        })
+    ));
+}
+
+#[test]
+fn test_convert_format() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(io::Cursor::new(data.clone())).unwrap();
+    let expected = region.load_chunk(14, 10).unwrap();
+
+    let level = flate2::Compression::fast();
+    let stats = region
+        .convert_format(CompressionFormat::Gzip, level)
+        .unwrap();
+    assert_eq!(stats.chunks_recompressed, 381);
+    let chunks: Vec<_> = region.chunks().collect();
+    for (x, z) in chunks {
+        assert_eq!(
+            region.get_compression_type(x, z).unwrap(),
+            CompressionFormat::Gzip
+        );
+        region.load_chunk(x, z).unwrap();
+    }
+    assert_eq!(region.load_chunk(14, 10).unwrap(), expected);
+
+    let mut region = RegionFile::new(io::Cursor::new(region.into_inner().into_inner())).unwrap();
+    region
+        .convert_format(CompressionFormat::Zlib, level)
+        .unwrap();
+    assert_eq!(region.count_chunks(), 381);
+    let chunks: Vec<_> = region.chunks().collect();
+    for (x, z) in chunks {
+        assert_eq!(
+            region.get_compression_type(x, z).unwrap(),
+            CompressionFormat::Zlib
+        );
+        region.load_chunk(x, z).unwrap();
+    }
+    assert_eq!(region.load_chunk(14, 10).unwrap(), expected);
+
+    region
+        .convert_format(CompressionFormat::Uncompressed, level)
+        .unwrap();
+    assert_eq!(
+        region.get_compression_type(14, 10).unwrap(),
+        CompressionFormat::Uncompressed
+    );
+    assert_eq!(region.load_chunk(14, 10).unwrap(), expected);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_convert_format_zstd() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(io::Cursor::new(data)).unwrap();
+    let expected = region.load_chunk(14, 10).unwrap();
+
+    region
+        .convert_format(CompressionFormat::Zstd, flate2::Compression::fast())
+        .unwrap();
+    assert_eq!(
+        region.get_compression_type(14, 10).unwrap(),
+        CompressionFormat::Zstd
+    );
+    assert_eq!(region.load_chunk(14, 10).unwrap(), expected);
+}
+
+#[test]
+fn test_junk_map() {
+    let f = std::fs::File::open("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(f).unwrap();
+    assert_eq!(
+        region.junk_map().unwrap(),
+        vec![((27, 6), 759), ((27, 9), 1019)]
+    );
+
+    let mut region =
+        RegionFile::new(io::Cursor::new(region_with_chunks(&[((0, 0), b"data")]))).unwrap();
+    assert!(region.junk_map().unwrap().is_empty());
+}
+
+#[test]
+fn test_clean_junk_stats() {
+    let mut data =
+        region_with_chunks(&[((0, 0), b"first"), ((1, 0), b"second"), ((2, 0), b"third")]);
+    // junk at the end of the first and the last chunk, the middle one is clean
+    data[3 * 4096 - 1] = 0xff;
+    data[5 * 4096 - 100] = 0xff;
+    // the last chunk is newer than the others
+    data[4096 + 8..4096 + 12].copy_from_slice(&1600000100u32.to_be_bytes());
+
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+    let first = region.junk_bytes(0, 0).unwrap();
+    let last = region.junk_bytes(2, 0).unwrap();
+    assert_eq!(
+        region
+            .clean_junk_in(ChunkRange::ALL, Some(1600000000))
+            .unwrap(),
+        CleanupStats {
+            chunks_cleaned: 1,
+            bytes_cleaned: last,
+            chunks_skipped: 2,
+        }
+    );
+    assert_eq!(region.junk_map().unwrap(), vec![((0, 0), first)]);
+
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    assert_eq!(
+        region.clean_junk_in(ChunkRange::ALL, None).unwrap(),
+        CleanupStats {
+            chunks_cleaned: 2,
+            bytes_cleaned: first + last,
+            chunks_skipped: 1,
+        }
+    );
+    assert!(region.junk_map().unwrap().is_empty());
+    assert_eq!(
+        region.clean_junk_in(ChunkRange::ALL, None).unwrap(),
+        CleanupStats {
+            chunks_cleaned: 0,
+            bytes_cleaned: 0,
+            chunks_skipped: 3,
+        }
+    );
+}
+
+/// Counts the calls to `read` on the reader it wraps
+#[cfg(test)]
+struct CountingReader<R> {
+    inner: R,
+    reads: std::rc::Rc<std::cell::Cell<usize>>,
+    seeks: std::rc::Rc<std::cell::Cell<usize>>,
+}
+
+#[cfg(test)]
+impl<R: io::Read> io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads.set(self.reads.get() + 1);
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+impl<R: io::Seek> io::Seek for CountingReader<R> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.seeks.set(self.seeks.get() + 1);
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+impl<R: io::Write> io::Write for CountingReader<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_read_calls() {
+    let reads = std::rc::Rc::new(std::cell::Cell::new(0));
+    let inner = io::Cursor::new(std::fs::read("tests/data/r.0.0.mca").unwrap());
+    let mut region = RegionFile::new(CountingReader {
+        inner,
+        reads: reads.clone(),
+        seeks: Default::default(),
+    })
+    .unwrap();
+    let chunks: Vec<(u8, u8)> = region.chunks().collect();
+
+    // the offset and timestamp tables take a read each
+    assert_eq!(reads.get(), 2);
+
+    // these used to take a read for the length, one for the compression type and one for the data
+    reads.set(0);
+    for (x, z) in &chunks {
+        region.load_chunk(*x, *z).unwrap();
+    }
+    assert_eq!(reads.get(), chunks.len() * 2);
+
+    reads.set(0);
+    region.junk_map().unwrap();
+    assert_eq!(reads.get(), chunks.len());
+
+    reads.set(0);
+    region.get_compression_type(14, 10).unwrap();
+    assert_eq!(reads.get(), 1);
+}
+
+#[test]
+fn test_header_parse() {
+    use byteorder::ReadBytesExt;
+
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let region = RegionFile::new(io::Cursor::new(data.clone())).unwrap();
+
+    // parse the header one entry at a time, the way it used to be done
+    let mut r = io::Cursor::new(data);
+    for idx in 0..1024 {
+        let v = r.read_u32::<BigEndian>().unwrap();
+        assert_eq!(region.offsets[idx], (v >> 8) * 4096);
+        assert_eq!(region.chunk_size[idx], (v & 0xff) as u8);
+    }
+    for idx in 0..1024 {
+        assert_eq!(region.timestamps[idx], r.read_u32::<BigEndian>().unwrap());
+    }
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_open_mmap() {
+    let path = Path::new("tests/data/r.0.0.mca");
+    let mut mapped = RegionFile::open_mmap(path).unwrap();
+    let mut region = RegionFile::new(std::fs::File::open(path).unwrap()).unwrap();
+
+    assert_eq!(mapped.offsets, region.offsets);
+    assert_eq!(mapped.timestamps, region.timestamps);
+    assert_eq!(mapped.chunk_size, region.chunk_size);
+    assert_eq!(mapped.info().unwrap(), region.info().unwrap());
+    assert_eq!(mapped.junk_map().unwrap(), region.junk_map().unwrap());
+    assert_eq!(
+        mapped.load_chunk(14, 10).unwrap(),
+        region.load_chunk(14, 10).unwrap()
+    );
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_chunk_slice() {
+    let path = Path::new("tests/data/r.0.0.mca");
+    let mut mapped = RegionFile::open_mmap(path).unwrap();
+    for (x, z) in mapped.chunks().collect::<Vec<_>>() {
+        let (_, raw) = mapped.read_chunk_raw(x, z).unwrap();
+        assert_eq!(mapped.chunk_slice(x, z).unwrap(), raw.as_slice());
+    }
+    assert_eq!(mapped.chunk_slice(31, 31), None);
+
+    // a length that runs past the chunk's sectors doesn't give a slice either
+    let dir = tempfile::tempdir().unwrap();
+    let corrupt = dir.path().join("r.0.0.mca");
+    let mut data = std::fs::read(path).unwrap();
+    let offset = mapped.get_chunk_offset(5, 3) as usize;
+    data[offset..offset + 4].copy_from_slice(&4096u32.to_be_bytes());
+    std::fs::write(&corrupt, data).unwrap();
+    let mapped = RegionFile::open_mmap(&corrupt).unwrap();
+    assert_eq!(mapped.chunk_slice(5, 3), None);
+    assert!(mapped.chunk_slice(14, 10).is_some());
+}
+
+#[test]
+fn test_mcr() {
+    let mut mca = RegionFile::new(std::fs::File::open("tests/data/r.0.0.mca").unwrap()).unwrap();
+    let data = std::fs::read("tests/data/r.0.0.mcr").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+
+    // the fixture holds a few of the chunks of the .mca fixture, compressed with gzip
+    let chunks: Vec<(u8, u8)> = region.chunks().collect();
+    assert_eq!(chunks, vec![(0, 0), (1, 0), (14, 10)]);
+    for (x, z) in &chunks {
+        assert_eq!(
+            region.get_compression_type(*x, *z).unwrap(),
+            CompressionFormat::Gzip
+        );
+        assert_eq!(
+            region.load_chunk(*x, *z).unwrap(),
+            mca.load_chunk(*x, *z).unwrap()
+        );
+    }
+
+    let options = RecompressOptions::new(flate2::Compression::best());
+    let stats = region.recompress_region(&options).unwrap();
+    assert_eq!(stats.chunks_recompressed, 3);
+    assert!(stats.bytes_after < stats.bytes_before);
+    for (x, z) in chunks {
+        assert_eq!(
+            region.get_compression_type(x, z).unwrap(),
+            CompressionFormat::Gzip
+        );
+        assert_eq!(
+            region.load_chunk(x, z).unwrap(),
+            mca.load_chunk(x, z).unwrap()
+        );
+    }
+}
+
+/// The fixture with the data of chunk (14, 10) overwritten with garbage
+#[cfg(test)]
+fn corrupt_fixture() -> Vec<u8> {
+    let mut data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let region = RegionFile::from_bytes(data.clone()).unwrap();
+    let offset = region.get_chunk_offset(14, 10) as usize;
+    for b in &mut data[offset + 5..offset + 105] {
+        *b = 0xff;
+    }
+    data
+}
+
+#[test]
+fn test_verify() {
+    let mut region =
+        RegionFile::from_bytes(std::fs::read("tests/data/r.0.0.mca").unwrap()).unwrap();
+    assert!(region.verify().is_empty());
+
+    let mut region = RegionFile::from_bytes(corrupt_fixture()).unwrap();
+    assert_eq!(region.verify(), vec![(14, 10)]);
+}
+
+#[test]
+fn test_continue_on_error() {
+    let mut options = RecompressOptions::new(flate2::Compression::best());
+    let mut region = RegionFile::from_bytes(corrupt_fixture()).unwrap();
+    assert!(region.recompress_region(&options).is_err());
+
+    options.continue_on_error = true;
+    let mut region = RegionFile::from_bytes(corrupt_fixture()).unwrap();
+    let stats = region.recompress_region(&options).unwrap();
+    assert_eq!(stats.failed_chunks, vec![(14, 10)]);
+    assert_eq!(stats.chunks_recompressed + stats.chunks_skipped, 380);
+    assert_eq!(region.verify(), vec![(14, 10)]);
+
+    let mut region = RegionFile::from_bytes(corrupt_fixture()).unwrap();
+    let mut out = io::Cursor::new(Vec::new());
+    let stats = region
+        .compact(&mut out, Some(&options), CompactOrder::Index)
+        .unwrap();
+    assert_eq!(stats.failed_chunks, vec![(14, 10)]);
+    assert_eq!(stats.chunks_recompressed + stats.chunks_skipped, 380);
+}
+
+#[test]
+fn test_compact_corrupt_length() {
+    // a length field that runs way past the sectors of the chunk
+    let mut data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let region = RegionFile::from_bytes(data.clone()).unwrap();
+    let offset = region.get_chunk_offset(14, 10) as usize;
+    let sectors = region.get_chunk_size(14, 10);
+    data[offset..offset + 4].copy_from_slice(&0xffff_ffffu32.to_be_bytes());
+    let raw = data[offset..offset + sectors].to_vec();
+
+    let mut options = RecompressOptions::new(flate2::Compression::best());
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+    let mut out = io::Cursor::new(Vec::new());
+    assert!(matches!(
+        region.compact(&mut out, Some(&options), CompactOrder::Index),
+        Err(Error::CorruptChunk { x: 14, z: 10 })
+    ));
+
+    // the sectors of the chunk are carried over as they were, both when recompressing and when
+    // the chunks are copied as is
+    options.continue_on_error = true;
+    for options in [Some(&options), None] {
+        let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+        let mut out = io::Cursor::new(Vec::new());
+        let stats = region
+            .compact(&mut out, options, CompactOrder::Index)
+            .unwrap();
+        assert_eq!(stats.failed_chunks, vec![(14, 10)]);
+        assert_eq!(stats.chunks_recompressed + stats.chunks_skipped, 380);
+
+        let mut compacted = RegionFile::from_bytes(out.into_inner()).unwrap();
+        assert_eq!(compacted.count_chunks(), 381);
+        assert_eq!(compacted.verify(), vec![(14, 10)]);
+        let offset = compacted.get_chunk_offset(14, 10) as usize;
+        let compacted = compacted.into_inner().into_inner();
+        assert_eq!(compacted[offset..offset + sectors], raw[..]);
+    }
+
+    // a chunk whose .mcc file is gone keeps its stub
+    let dir = tempfile::tempdir().unwrap();
+    let mut region =
+        RegionFile::from_bytes(std::fs::read("tests/data/r.0.0.mca").unwrap()).unwrap();
+    region.set_external_dir(dir.path().to_path_buf(), 0, 0);
+    region
+        .write_chunk(14, 10, &incompressible(1100 * 1024), 1600000000)
+        .unwrap();
+    std::fs::remove_file(region.external_chunk_path(14, 10).unwrap()).unwrap();
+    let mut out = io::Cursor::new(Vec::new());
+    let stats = region.compact(&mut out, None, CompactOrder::Index).unwrap();
+    assert_eq!(stats.failed_chunks, vec![(14, 10)]);
+    let mut compacted = RegionFile::from_bytes(out.into_inner()).unwrap();
+    assert_eq!(
+        compacted.read_chunk_header(14, 10).unwrap(),
+        (1, CompressionFormat::Zlib.to_byte() | EXTERNAL_FLAG)
+    );
+}
+
+#[test]
+fn test_info() {
+    use std::fs::File;
+
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(f).unwrap();
+    assert_eq!(region.count_chunks(), 381);
+    assert_eq!(
+        region.get_compression_type(14, 10).unwrap(),
+        CompressionFormat::Zlib
+    );
+
+    let report = region.info().unwrap();
+    assert_eq!(report.chunks, 381);
+    assert_eq!(report.total_sectors, 398);
+    assert_eq!(report.used_sectors, 398);
+    assert_eq!(report.allocated_bytes, (366 + 15 * 2) * 4096);
+    assert!(report.data_bytes < report.allocated_bytes);
+    assert_eq!(report.compression_types.get(&2), Some(&381));
+    assert_eq!(report.compression_types.len(), 1);
+    assert_eq!(report.oldest_timestamp, Some(1383443693));
+    assert_eq!(report.newest_timestamp, Some(1383443713));
+}
+
+#[test]
+fn test_sector_usage() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(io::Cursor::new(data)).unwrap();
+    let report = region.info().unwrap();
+
+    let usage = region.sector_usage().unwrap();
+    assert_eq!(usage.used_sectors, 2 + 366 + 15 * 2);
+    assert_eq!(usage.occupied.len(), 398);
+    assert!(usage.occupied.iter().all(|v| *v));
+    assert_eq!(
+        usage.wasted_bytes,
+        report.allocated_bytes - report.data_bytes
+    );
+
+    // a deleted chunk leaves a gap behind
+    let sector = region.get_chunk_offset(14, 10) as usize / 4096;
+    let sectors = region.get_chunk_size(14, 10) / 4096;
+    region.delete_chunk(14, 10).unwrap();
+    let usage = region.sector_usage().unwrap();
+    assert_eq!(usage.used_sectors, 398 - sectors);
+    assert!(!usage.occupied[sector]);
+}
+
+#[test]
+fn test_chunks() {
+    use std::fs::File;
+
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let region = RegionFile::new(f).unwrap();
+
+    let chunks: Vec<(u8, u8)> = region.chunks().collect();
+    assert_eq!(chunks.len(), region.count_chunks());
+    assert_eq!(chunks[0], (0, 0));
+    assert!(chunks.contains(&(14, 10)));
+    assert!(!chunks.contains(&(15, 15)));
+}
+
+#[test]
+fn test_load_chunk() {
+    use std::fs::File;
+
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(f).unwrap();
+
+    let data = region.load_chunk(0, 0).unwrap();
+    assert_eq!(data.len(), 52994);
+    // an unnamed compound tag
+    assert_eq!(&data[..3], &[10, 0, 0]);
+
+    assert!(matches!(
+        region.load_chunk(15, 15),
+        Err(Error::MissingChunk { x: 15, z: 15 })
+    ));
+}
+
+#[cfg(feature = "nbt")]
+#[test]
+fn test_load_chunk_nbt() {
+    use std::fs::File;
+
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(f).unwrap();
+
+    let root = match region.load_chunk_nbt(0, 0).unwrap() {
+        fastnbt::Value::Compound(root) => root,
+        _ => panic!("chunk isn't a compound"),
+    };
+    let keys: Vec<&String> = root.keys().collect();
+    assert_eq!(keys, vec!["Level"]);
+
+    match &root["Level"] {
+        fastnbt::Value::Compound(level) => {
+            assert!(level.contains_key("Entities"));
+            assert!(level.contains_key("Sections"));
+            assert_eq!(level["xPos"], fastnbt::Value::Int(0));
+            assert_eq!(level["zPos"], fastnbt::Value::Int(0));
+        }
+        _ => panic!("Level isn't a compound"),
+    }
+}
+
+/// Builds a region file in memory containing the given chunks, compressed with zlib
+#[cfg(test)]
+fn region_with_chunks(chunks: &[((u8, u8), &[u8])]) -> Vec<u8> {
+    let mut out = vec![0; 8192];
+    for ((x, z), data) in chunks {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        io::Write::write_all(&mut encoder, data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let idx = *x as usize + *z as usize * 32;
+        let sector = out.len() / 4096;
+        let sectors = (compressed.len() + 5).div_ceil(4096);
+        out[idx * 4..idx * 4 + 4].copy_from_slice(&((sector << 8 | sectors) as u32).to_be_bytes());
+        out[4096 + idx * 4..4096 + idx * 4 + 4].copy_from_slice(&1600000000u32.to_be_bytes());
+
+        out.extend_from_slice(&(compressed.len() as u32 + 1).to_be_bytes());
+        out.push(2);
+        out.extend_from_slice(&compressed);
+        out.resize((sector + sectors) * 4096, 0);
+    }
+    out
+}
+
+#[cfg(feature = "nbt")]
+#[test]
+fn test_chunk_data_version() {
+    use std::collections::HashMap;
+    use std::fs::File;
+
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(f).unwrap();
+
+    // the fixture predates the DataVersion tag
+    assert!(matches!(
+        region.chunk_data_version(0, 0),
+        Err(Error::MissingTag {
+            name: "DataVersion"
+        })
+    ));
+    let report = region.info().unwrap();
+    assert!(report.data_versions.is_empty());
+    assert_eq!(report.chunks_without_data_version, 381);
+
+    let chunk = |version: i32| {
+        let mut root = HashMap::new();
+        root.insert("DataVersion".to_string(), fastnbt::Value::Int(version));
+        fastnbt::to_bytes(&fastnbt::Value::Compound(root)).unwrap()
+    };
+    let data = region_with_chunks(&[
+        ((0, 0), &chunk(2586)),
+        ((1, 0), &chunk(2586)),
+        ((5, 3), &chunk(3465)),
+    ]);
+    let mut region = RegionFile::new(io::Cursor::new(data)).unwrap();
+    assert_eq!(region.chunk_data_version(5, 3).unwrap(), 3465);
+
+    let report = region.info().unwrap();
+    assert_eq!(report.data_versions.get(&2586), Some(&2));
+    assert_eq!(report.data_versions.get(&3465), Some(&1));
+    assert_eq!(report.data_versions.len(), 2);
+}
+
+#[test]
+fn test_in_memory_region() {
+    let data = region_with_chunks(&[((3, 4), b"hello"), ((31, 31), &[0; 10000])]);
+    let mut region = RegionFile::new(io::Cursor::new(data)).unwrap();
+
+    assert_eq!(region.count_chunks(), 2);
+    assert_eq!(region.load_chunk(3, 4).unwrap(), b"hello");
+    assert_eq!(region.load_chunk(31, 31).unwrap(), vec![0; 10000]);
+    assert_eq!(region.get_chunk_timestamp(3, 4), Some(1600000000));
+}
+
+#[test]
+fn test_from_bytes() {
+    let data = region_with_chunks(&[((3, 4), b"hello"), ((31, 31), &[0; 10000])]);
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+    assert_eq!(region.count_chunks(), 2);
+
+    // "hello" comes out the same at any level, so only the zeros actually get rewritten
+    let options = RecompressOptions::new(flate2::Compression::fast());
+    let stats = region.recompress_region(&options).unwrap();
+    assert_eq!(stats.chunks_recompressed, 1);
+    assert_eq!(stats.chunks_skipped, 1);
+
+    let bytes = region.into_inner().into_inner();
+    assert_eq!(bytes.len(), data.len());
+    assert_ne!(bytes, data);
+    let mut region = RegionFile::from_bytes(bytes).unwrap();
+    assert_eq!(region.load_chunk(3, 4).unwrap(), b"hello");
+    assert_eq!(region.load_chunk(31, 31).unwrap(), vec![0; 10000]);
+}
+
+#[test]
+fn test_delete_chunk() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(io::Cursor::new(data)).unwrap();
+
+    region.delete_chunk(14, 10).unwrap();
+    assert!(!region.chunk_exists(14, 10));
+    assert_eq!(region.get_chunk_timestamp(14, 10), Some(0));
+
+    let data = region.into_inner().into_inner();
+    let region = RegionFile::new(io::Cursor::new(data)).unwrap();
+    assert!(!region.chunk_exists(14, 10));
+    assert!(region.chunk_exists(0, 0));
+    assert_eq!(region.count_chunks(), 380);
+}
+
+#[cfg(feature = "nbt")]
+#[test]
+fn test_chunks_below_inhabited() {
+    use std::fs::File;
+
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(f).unwrap();
+
+    assert_eq!(region.chunk_inhabited_time(0, 0).unwrap(), 0);
+    assert_eq!(region.chunk_inhabited_time(27, 13).unwrap(), 58);
+
+    let chunks = region.chunks_below_inhabited(1).unwrap();
+    assert_eq!(chunks.len(), 339);
+    assert!(chunks.contains(&(0, 0)));
+    assert!(!chunks.contains(&(27, 13)));
+
+    let chunks = region.chunks_below_inhabited(100).unwrap();
+    assert_eq!(chunks.len(), 341);
+    assert!(chunks.contains(&(27, 13)));
+}
+
+#[test]
+fn test_write_chunk() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(io::Cursor::new(data)).unwrap();
+    let original = region.load_chunk(1, 0).unwrap();
+
+    // fits in the existing sector
+    region.write_chunk(0, 0, b"small", 42).unwrap();
+    assert_eq!(region.get_chunk_offset(0, 0), 180224);
+
+    // noise doesn't compress, so this needs to be relocated
+    let mut seed: u32 = 1;
+    let noise: Vec<u8> = (0..10000)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect();
+    region.write_chunk(14, 10, &noise, 43).unwrap();
+    region.write_chunk(15, 15, b"new", 44).unwrap();
+
+    let data = region.into_inner().into_inner();
+    assert_eq!(data.len() % 4096, 0);
+    let mut region = RegionFile::new(io::Cursor::new(data)).unwrap();
+    assert_eq!(region.load_chunk(0, 0).unwrap(), b"small");
+    assert_eq!(region.load_chunk(14, 10).unwrap(), noise);
+    assert_eq!(region.get_chunk_size(14, 10), 3 * 4096);
+    assert_eq!(region.load_chunk(15, 15).unwrap(), b"new");
+    assert_eq!(region.load_chunk(1, 0).unwrap(), original);
+    assert_eq!(region.get_chunk_timestamp(0, 0), Some(42));
+    assert_eq!(region.get_chunk_timestamp(14, 10), Some(43));
+    assert_eq!(region.get_chunk_timestamp(15, 15), Some(44));
+    assert_eq!(region.count_chunks(), 382);
+}
+
+#[test]
+fn test_set_chunk_timestamp() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(io::Cursor::new(data)).unwrap();
+    let neighbour = region.get_chunk_timestamp(15, 10);
+
+    region.set_chunk_timestamp(14, 10, 1700000000).unwrap();
+    assert_eq!(region.get_chunk_timestamp(14, 10), Some(1700000000));
+    assert_eq!(region.get_chunk_timestamp(15, 10), neighbour);
+
+    let data = region.into_inner().into_inner();
+    let idx = 14 + 10 * 32;
+    assert_eq!(
+        data[4096 + idx * 4..4096 + idx * 4 + 4],
+        1700000000u32.to_be_bytes()
+    );
+    let region = RegionFile::new(io::Cursor::new(data)).unwrap();
+    assert_eq!(region.get_chunk_timestamp(14, 10), Some(1700000000));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    use std::fs::File;
+
+    let stats = RecompressStats {
+        bytes_before: 1000,
+        bytes_after: 800,
+        chunks_recompressed: 3,
+        chunks_skipped: 1,
+        failed_chunks: vec![(14, 10)],
+        sectors_before: 398,
+        sectors_after: 397,
+    };
+    let json = serde_json::to_string(&stats).unwrap();
+    assert_eq!(
+        json,
+        "{\"bytes_before\":1000,\"bytes_after\":800,\"chunks_recompressed\":3,\"chunks_skipped\":1,\"failed_chunks\":[[14,10]],\"sectors_before\":398,\"sectors_after\":397}"
+    );
+    assert_eq!(
+        serde_json::from_str::<RecompressStats>(&json).unwrap(),
+        stats
+    );
+
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let report = RegionFile::new(f).unwrap().info().unwrap();
+    let json = serde_json::to_string(&report).unwrap();
+    assert_eq!(serde_json::from_str::<InfoReport>(&json).unwrap(), report);
+}
+
+#[test]
+fn test_parse_region_coords() {
+    assert_eq!(parse_region_coords(Path::new("r.0.0.mca")), Some((0, 0)));
+    assert_eq!(
+        parse_region_coords(Path::new("world/region/r.-1.5.mca")),
+        Some((-1, 5))
+    );
+    assert_eq!(
+        parse_region_coords(Path::new("r.-12.-300.mca")),
+        Some((-12, -300))
+    );
+    assert_eq!(parse_region_coords(Path::new("level.dat")), None);
+    assert_eq!(parse_region_coords(Path::new("r.0.mca")), None);
+    assert_eq!(parse_region_coords(Path::new("r.0.0.0.mca")), None);
+    assert_eq!(parse_region_coords(Path::new("r.a.0.mca")), None);
+    assert_eq!(parse_region_coords(Path::new("r.0.0.mca.bak")), None);
+    assert_eq!(parse_region_coords(Path::new("x.0.0.mca")), None);
+    assert_eq!(parse_region_coords(Path::new("r.2.-3.mcr")), Some((2, -3)));
+}
+
+#[test]
+fn test_recompress_single_chunk() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+
+    let stats = region
+        .recompress_chunk(14, 10, flate2::Compression::best())
+        .unwrap();
+    assert_eq!(stats.chunks_recompressed, 1);
+
+    // only the sectors of (14, 10) changed
+    let offset = region.get_chunk_offset(14, 10) as usize;
+    let size = region.get_chunk_size(14, 10);
+    let after = region.into_inner().into_inner();
+    assert_ne!(&after[offset..offset + size], &data[offset..offset + size]);
+    assert_eq!(&after[..offset], &data[..offset]);
+    assert_eq!(&after[offset + size..], &data[offset + size..]);
+
+    let mut region = RegionFile::from_bytes(after).unwrap();
+    assert!(matches!(
+        region.recompress_chunk(15, 15, flate2::Compression::best()),
+        Err(Error::MissingChunk { x: 15, z: 15 })
+    ));
+    assert!(matches!(
+        region.recompress_chunk(32, 0, flate2::Compression::best()),
+        Err(Error::MissingChunk { x: 32, z: 0 })
+    ));
+}
+
+#[test]
+fn test_zero_fill() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+
+    // leave some garbage behind in the header and in the sectors of a deleted chunk
+    let offset = region.get_chunk_offset(14, 10) as usize;
+    let size = region.get_chunk_size(14, 10);
+    region.delete_chunk(14, 10).unwrap();
+    region.set_chunk_timestamp(14, 10, 1600000000).unwrap();
+    region
+        .cursor
+        .inner
+        .get_mut()
+        .extend_from_slice(&[0xff; 100]);
+
+    assert!(region.zero_fill().unwrap() >= size + 100 + 4);
+    assert_eq!(region.junk_map().unwrap(), vec![]);
+
+    // mark the bytes that belong to the length, type and data of every chunk
+    let mut payload = vec![false; region.cursor.inner.get_ref().len()];
+    let chunks: Vec<(u8, u8)> = region.chunks().collect();
+    for (x, z) in chunks {
+        let start = region.get_chunk_offset(x, z) as usize;
+        let (total_len, _) = region.read_chunk_header(x, z).unwrap();
+        for b in &mut payload[start..start + 4 + total_len] {
+            *b = true;
+        }
+    }
+    let bytes = region.into_inner().into_inner();
+    assert!(bytes[offset..offset + size].iter().all(|b| *b == 0));
+    assert!(bytes[bytes.len() - 100..].iter().all(|b| *b == 0));
+    for (i, byte) in bytes.iter().enumerate().skip(8192) {
+        assert!(payload[i] || *byte == 0, "byte {} isn't zeroed", i);
+    }
+}
+
+#[test]
+fn test_duplicate_offsets() {
+    let mut data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+    assert_eq!(region.duplicate_offsets(), vec![]);
+
+    // point (3, 4) and (5, 6) at the sectors of (0, 0)
+    for idx in &[3 + 4 * 32, 5 + 6 * 32] {
+        data.copy_within(0..4, idx * 4);
+    }
+    region = RegionFile::from_bytes(data.clone()).unwrap();
+    assert_eq!(
+        region.duplicate_offsets(),
+        vec![((0, 0), (3, 4)), ((0, 0), (5, 6))]
+    );
+
+    let options = RecompressOptions::new(flate2::Compression::best());
+    assert!(matches!(
+        region.recompress_region(&options),
+        Err(Error::DuplicateOffset {
+            first: (0, 0),
+            second: (3, 4)
+        })
+    ));
+    assert_eq!(region.into_inner().into_inner(), data);
+}
+
+#[test]
+fn test_modified_after() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+
+    let chunks: Vec<(u8, u8)> = region.chunks().collect();
+    let mut timestamps: Vec<u32> = chunks
+        .iter()
+        .map(|(x, z)| region.get_chunk_timestamp(*x, *z).unwrap())
+        .collect();
+    timestamps.sort_unstable();
+    let cutoff = timestamps[timestamps.len() / 2];
+    let newer = timestamps.iter().filter(|ts| **ts > cutoff).count();
+    assert!(newer > 0 && newer < chunks.len());
+
+    let mut options = RecompressOptions::new(flate2::Compression::best());
+    options.modified_after = Some(cutoff);
+    let stats = region.recompress_region(&options).unwrap();
+    assert!(stats.chunks_recompressed > 0);
+    assert!(stats.chunks_recompressed <= newer);
+    assert!(stats.chunks_skipped >= chunks.len() - newer);
+
+    let mut original = RegionFile::from_bytes(data.clone()).unwrap();
+    for &(x, z) in &chunks {
+        if region.get_chunk_timestamp(x, z).unwrap() <= cutoff {
+            assert_eq!(
+                region.read_chunk_raw(x, z).unwrap(),
+                original.read_chunk_raw(x, z).unwrap()
+            );
+        }
+    }
+
+    // the chunks with junk in them are cleaned up only when they're new enough
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    let junk = region.junk_map().unwrap();
+    let newest = junk
+        .iter()
+        .map(|((x, z), _)| region.get_chunk_timestamp(*x, *z).unwrap())
+        .max()
+        .unwrap();
+    region.clean_junk_modified_after(Some(newest)).unwrap();
+    assert_eq!(region.junk_map().unwrap(), junk);
+    region.clean_junk_modified_after(Some(newest - 1)).unwrap();
+    assert!(region.junk_map().unwrap().len() < junk.len());
+}
+
+/// Deterministic bytes that zlib can't make any smaller
+#[cfg(test)]
+fn incompressible(len: usize) -> Vec<u8> {
+    let mut state: u32 = 0x1234_5678;
+    (0..len)
+        .map(|_| {
+            // xorshift
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_external_chunk() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let large = incompressible(1100 * 1024);
+
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+    assert!(matches!(
+        region.write_chunk(14, 10, &large, 1600000000),
+        Err(Error::ChunkTooLarge { .. })
+    ));
+
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    region.set_external_dir(dir.path().to_path_buf(), -1, 2);
+    region.write_chunk(14, 10, &large, 1600000000).unwrap();
+
+    // chunk (14, 10) of region (-1, 2) is chunk (-18, 74) of the world
+    let path = dir.path().join("c.-18.74.mcc");
+    assert_eq!(region.external_chunk_path(14, 10), Some(path.clone()));
+    let mut other = RegionFile::from_bytes(vec![0; 8192]).unwrap();
+    other.set_region_path(&dir.path().join("r.-1.2.mca"));
+    assert_eq!(other.external_chunk_path(14, 10), Some(path.clone()));
+    assert!(std::fs::metadata(&path).unwrap().len() > 255 * 4096);
+    assert_eq!(region.get_chunk_size(14, 10), 4096);
+    assert_eq!(
+        region.read_chunk_header(14, 10).unwrap(),
+        (1, CompressionFormat::Zlib.to_byte() | EXTERNAL_FLAG)
+    );
+    assert_eq!(
+        region.get_compression_type(14, 10).unwrap(),
+        CompressionFormat::Zlib
+    );
+    assert_eq!(region.load_chunk(14, 10).unwrap(), large);
+
+    // recompressing leaves the external chunk alone, and a compacted copy still points at it
+    let options = RecompressOptions::new(flate2::Compression::fast());
+    let stats = region.recompress_chunk_options(14, 10, &options).unwrap();
+    assert_eq!(stats.chunks_skipped, 1);
+    let mut out = io::Cursor::new(Vec::new());
+    region.compact(&mut out, None, CompactOrder::Index).unwrap();
+    let mut compacted = RegionFile::from_bytes(out.into_inner()).unwrap();
+    assert!(matches!(
+        compacted.load_chunk(14, 10),
+        Err(Error::ExternalChunk { x: 14, z: 10 })
+    ));
+    compacted.set_external_dir(dir.path().to_path_buf(), -1, 2);
+    assert_eq!(compacted.load_chunk(14, 10).unwrap(), large);
+}
+
+#[test]
+fn test_mcc_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let mcc_dir = dir.path().join("mcc");
+    std::fs::create_dir(&mcc_dir).unwrap();
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let large = incompressible(1100 * 1024);
+
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    region.set_region_path_with_mcc_dir(&dir.path().join("r.-1.2.mca"), Some(&mcc_dir));
+    region.write_chunk(14, 10, &large, 1600000000).unwrap();
+    assert!(mcc_dir.join("c.-18.74.mcc").exists());
+    assert!(!dir.path().join("c.-18.74.mcc").exists());
+    assert_eq!(region.load_chunk(14, 10).unwrap(), large);
+
+    // once it fits again it moves back in, and the .mcc file is removed from that directory
+    region.write_chunk(14, 10, b"small", 1600000000).unwrap();
+    assert!(!mcc_dir.join("c.-18.74.mcc").exists());
+
+    // without one it's the directory of the region file
+    region.set_region_path_with_mcc_dir(&dir.path().join("r.-1.2.mca"), None);
+    assert_eq!(
+        region.external_chunk_path(14, 10),
+        Some(dir.path().join("c.-18.74.mcc"))
+    );
+}
+
+#[test]
+fn test_internalize_chunk() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    region.set_external_dir(dir.path().to_path_buf(), 0, 0);
+    assert!(!region.internalize_chunk(14, 10).unwrap());
+
+    region
+        .write_chunk(14, 10, &incompressible(1100 * 1024), 1600000000)
+        .unwrap();
+    let path = region.external_chunk_path(14, 10).unwrap();
+    assert!(path.exists());
+    assert_eq!(region.external_chunks().unwrap(), vec![(14, 10)]);
+    assert!(!region.internalize_chunk(14, 10).unwrap());
+
+    // the chunk shrinks, like when the game saved it again after some blocks were removed
+    let small = vec![7; 100 * 1024];
+    let compressed = compress(CompressionFormat::Zlib, &small, flate2::Compression::fast());
+    std::fs::write(&path, compressed.unwrap()).unwrap();
+
+    // compacting moves it into the new file, but leaves the .mcc file in place
+    let mut out = io::Cursor::new(Vec::new());
+    region.compact(&mut out, None, CompactOrder::Index).unwrap();
+    assert!(path.exists());
+    let mut compacted = RegionFile::from_bytes(out.into_inner()).unwrap();
+    assert_eq!(
+        compacted.get_compression_type(14, 10).unwrap(),
+        CompressionFormat::Zlib
+    );
+    assert_eq!(compacted.load_chunk(14, 10).unwrap(), small);
+
+    assert!(region.internalize_chunk(14, 10).unwrap());
+    assert!(!path.exists());
+    assert!(region.external_chunks().unwrap().is_empty());
+    assert_eq!(
+        region.read_chunk_header(14, 10).unwrap().1,
+        CompressionFormat::Zlib.to_byte()
+    );
+    assert_eq!(region.load_chunk(14, 10).unwrap(), small);
+    let mut reopened = RegionFile::from_bytes(region.into_inner().into_inner()).unwrap();
+    assert_eq!(reopened.load_chunk(14, 10).unwrap(), small);
+    assert_eq!(reopened.verify(), vec![]);
+}
+
+#[test]
+fn test_recompress_grown_chunk() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+
+    // the fixture was compressed with a high level, so plenty of chunks grow at the lowest one
+    let options = RecompressOptions::new(flate2::Compression::fast());
+    let stats = region.recompress_region(&options).unwrap();
+    assert!(stats.chunks_skipped > 0);
+    assert!(stats.chunks_recompressed > 0);
+    assert_eq!(region.verify(), vec![]);
+}
+
+#[test]
+fn test_recompress_exact_fit() {
+    // without compression the chunk grows to cover its sector up to the very last byte
+    let none = flate2::Compression::none();
+    let raw = (4000..4091)
+        .map(|len| vec![7u8; len])
+        .find(|raw| {
+            let mut out = Vec::new();
+            encode(CompressionFormat::Zlib, raw.as_slice(), none, &mut out).unwrap();
+            out.len() == 4096 - 5
+        })
+        .unwrap();
+    let mut region = RegionFile::from_bytes(region_with_chunks(&[((0, 0), &raw)])).unwrap();
+    assert_eq!(region.get_chunk_size(0, 0), 4096);
+
+    let stats = region
+        .recompress_region(&RecompressOptions::new(none))
+        .unwrap();
+    assert_eq!(stats.chunks_recompressed, 1);
+    assert_eq!(stats.bytes_after, 4096 - 4);
+    assert_eq!(region.read_chunk_header(0, 0).unwrap().0, 4096 - 4);
+    assert_eq!(region.get_chunk_size(0, 0), 4096);
+    assert_eq!(region.load_chunk(0, 0).unwrap(), raw);
+    assert_eq!(region.verify(), vec![]);
+}
+
+#[test]
+fn test_recompress_with_sizes() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+
+    let options = RecompressOptions::new(flate2::Compression::best());
+    let (stats, sizes) = region.recompress_region_with_sizes(&options).unwrap();
+    assert_eq!(
+        sizes.len(),
+        stats.chunks_recompressed + stats.chunks_skipped
+    );
+    assert_eq!(sizes.len(), 381);
+    assert_eq!(
+        sizes.iter().map(|s| s.before).sum::<usize>(),
+        stats.bytes_before
+    );
+    assert_eq!(
+        sizes.iter().map(|s| s.after).sum::<usize>(),
+        stats.bytes_after
+    );
+
+    let (x, z) = (sizes[0].x, sizes[0].z);
+    assert!(region.chunk_exists(x, z));
+    assert_eq!(region.read_chunk_header(x, z).unwrap().0, sizes[0].after);
+}
+
+#[test]
+fn test_chunk_entries() {
+    let f = std::fs::File::open("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(f).unwrap();
+    let entries = region.chunk_entries().unwrap();
+    assert_eq!(entries.len(), 381);
+
+    let entry = entries.iter().find(|e| (e.x, e.z) == (27, 6)).unwrap();
+    assert_eq!(entry.offset, region.get_chunk_offset(27, 6));
+    assert_eq!(entry.sectors as usize * 4096, region.get_chunk_size(27, 6));
+    assert_eq!(entry.junk_bytes, 759);
+    assert_eq!(entry.compression_type, 2);
+    assert_eq!(
+        entry.data_length,
+        region.read_chunk_header(27, 6).unwrap().0
+    );
+    assert_eq!(
+        entries.iter().map(|e| e.junk_bytes).sum::<usize>(),
+        759 + 1019
+    );
+}
+
+#[test]
+fn test_seek_calls() {
+    let seeks = std::rc::Rc::new(std::cell::Cell::new(0));
+    let inner = io::Cursor::new(std::fs::read("tests/data/r.0.0.mca").unwrap());
+    let mut region = RegionFile::new(CountingReader {
+        inner,
+        reads: Default::default(),
+        seeks: seeks.clone(),
+    })
+    .unwrap();
+    let chunks: Vec<(u8, u8)> = region.chunks().collect();
+
+    // reading a chunk ends right where the next one in the file starts, so walking the chunks in
+    // the order they're stored in only takes a seek to the first one
+    let mut by_offset = chunks.clone();
+    by_offset.sort_by_key(|(x, z)| region.get_chunk_offset(*x, *z));
+    seeks.set(0);
+    for (x, z) in &by_offset {
+        region.junk_bytes(*x, *z).unwrap();
+    }
+    assert_eq!(seeks.get(), 1);
+
+    // the header of the same chunk is only sought once, and recompressing seeks back to the start
+    // of the chunk just once to write it
+    seeks.set(0);
+    region.read_chunk_header(14, 10).unwrap();
+    region.read_chunk_header(14, 10).unwrap();
+    assert_eq!(seeks.get(), 2);
+
+    let options = RecompressOptions::new(flate2::Compression::best());
+    let before = region.load_chunk(14, 10).unwrap();
+    seeks.set(0);
+    region.recompress_chunk_options(14, 10, &options).unwrap();
+    assert_eq!(seeks.get(), 2);
+    assert_eq!(region.load_chunk(14, 10).unwrap(), before);
+}
+
+#[test]
+fn test_writes_end_on_sector_boundary() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    let options = RecompressOptions::new(flate2::Compression::best());
+
+    // this holds without debug assertions too, for chunks of more than one sector as well
+    let (x, z) = region
+        .chunks()
+        .find(|(x, z)| region.get_chunk_size(*x, *z) == 8192)
+        .unwrap();
+    let end = region.get_chunk_offset(x, z) as u64 + 8192;
+    let stats = region.recompress_chunk_options(x, z, &options).unwrap();
+    assert_eq!(stats.chunks_recompressed, 1);
+    assert_eq!(region.cursor.stream_position().unwrap(), end);
+    assert_eq!(region.cursor.inner.position(), end);
+
+    region.clean_chunk(x, z).unwrap();
+    assert_eq!(region.cursor.inner.position(), end);
+
+    assert!(region.check_chunk_end(end).is_ok());
+    assert!(region.check_chunk_end(end - 1).is_err());
+}
+
+#[test]
+fn test_broken_header_entries() {
+    // a header entry for (0, 0) pointing at sector 2, with `sectors` sectors and a chunk in there
+    // claiming to be `total_len` bytes long
+    let region_with = |sectors: u8, total_len: u32| {
+        let mut data = vec![0; 3 * 4096];
+        data[..4].copy_from_slice(&(2 << 8 | sectors as u32).to_be_bytes());
+        data[8192..8196].copy_from_slice(&total_len.to_be_bytes());
+        data[8196] = 2;
+        RegionFile::from_bytes(data).unwrap()
+    };
+    let options = RecompressOptions::new(flate2::Compression::best());
+
+    let broken = [
+        // no sectors at all, which is less than even the length and compression type take up
+        (0, 10),
+        // a length of 0, which doesn't even leave room for the compression type
+        (1, 0),
+        // a length that's larger than the sectors the chunk has
+        (1, u32::MAX),
+        // passed the old `chunk_size > total_len` check, but still ends past the sector
+        (1, 4095),
+    ];
+    for (sectors, total_len) in broken {
+        let mut region = region_with(sectors, total_len);
+        let corrupt = |res| matches!(res, Err(Error::CorruptChunk { x: 0, z: 0 }));
+        assert!(region.chunk_exists(0, 0));
+        assert!(corrupt(region.junk_bytes(0, 0).map(|_| ())));
+        assert!(corrupt(region.load_chunk(0, 0).map(|_| ())));
+        assert_eq!(region.verify(), vec![(0, 0)]);
+        assert!(corrupt(region.clean_junk().map(|_| ())));
+        assert!(corrupt(
+            region.recompress_chunk_options(0, 0, &options).map(|_| ())
+        ));
+
+        // when continuing on errors it's just another chunk that failed
+        let mut options = options;
+        options.continue_on_error = true;
+        let stats = region.recompress_region(&options).unwrap();
+        assert_eq!(stats.failed_chunks, vec![(0, 0)]);
+    }
+
+    // a length that exactly fills the sector is fine
+    let mut region = region_with(1, 4092);
+    assert_eq!(region.junk_bytes(0, 0).unwrap(), 0);
+    assert_eq!(region.clean_junk().unwrap(), 0);
+
+    // an offset that doesn't fit in a u32 as bytes used to overflow
+    let mut data = vec![0; 2 * 4096];
+    data[..4].copy_from_slice(&(0xff_ffff << 8 | 1u32).to_be_bytes());
+    assert!(matches!(RegionFile::from_bytes(data), Err(Error::Io(_))));
+}
+
+#[test]
+fn test_recompress_filtered() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    let options = RecompressOptions::new(flate2::Compression::best());
+
+    let clean = region.read_chunk_stored(14, 10).unwrap();
+    let mut seen = Vec::new();
+    let stats = region
+        .recompress_region_filtered(
+            &options,
+            |region, x, z| Ok(region.junk_bytes(x, z)? > 0),
+            |x, z, _, _| seen.push((x, z)),
+        )
+        .unwrap();
+    assert_eq!(seen.len(), region.count_chunks());
+    assert_eq!(stats.chunks_recompressed, 2);
+    assert_eq!(stats.chunks_skipped, region.count_chunks() - 2);
+
+    // the chunk without junk is left exactly as it was, while the junk is gone from the others
+    assert_eq!(region.read_chunk_stored(14, 10).unwrap(), clean);
+    assert_eq!(region.junk_bytes(27, 6).unwrap(), 0);
+    assert_eq!(region.junk_bytes(27, 9).unwrap(), 0);
+}
+
+#[test]
+fn test_chunk_sectors() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let region = RegionFile::from_bytes(data.clone()).unwrap();
+
+    for (x, z) in (0..1024).map(|idx| ((idx % 32) as u8, (idx / 32) as u8)) {
+        let idx = (x as usize + z as usize * 32) * 4;
+        assert_eq!(region.get_chunk_sectors(x, z), data[idx + 3]);
+    }
+    assert_eq!(region.get_chunk_sectors(14, 10), 1);
+    let two = region
+        .chunks()
+        .filter(|(x, z)| region.get_chunk_sectors(*x, *z) == 2)
+        .count();
+    assert_eq!(two, 15);
+}
+
+#[test]
+fn test_chunk_sector_range() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+
+    // the first header entry is 0x00002c01
+    assert_eq!(region.chunk_sector_range(0, 0), Some((44, 1)));
+    assert_eq!(
+        region.chunk_sector_range(0, 0).unwrap().0 * 4096,
+        region.get_chunk_offset(0, 0)
+    );
+
+    region.delete_chunk(0, 0).unwrap();
+    assert_eq!(region.chunk_sector_range(0, 0), None);
+}
+
+#[cfg(feature = "nbt")]
+#[test]
+fn test_chunk_entity_counts() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+
+    // the fixture still has both lists inside of Level
+    assert_eq!(region.chunk_entity_counts(1, 15).unwrap(), (3, 0));
+    assert_eq!(region.chunk_entity_counts(6, 16).unwrap(), (0, 8));
+    assert_eq!(region.chunk_entity_counts(14, 10).unwrap(), (0, 0));
+
+    let list =
+        |len: usize| fastnbt::Value::List(vec![fastnbt::Value::Compound(HashMap::new()); len]);
+    let mut chunk = HashMap::new();
+    chunk.insert("block_entities".to_string(), list(2));
+    let chunk = fastnbt::to_bytes(&fastnbt::Value::Compound(chunk)).unwrap();
+    let mut entities = HashMap::new();
+    entities.insert("Entities".to_string(), list(5));
+    let entities = fastnbt::to_bytes(&fastnbt::Value::Compound(entities)).unwrap();
+
+    let data = region_with_chunks(&[((0, 0), &chunk), ((1, 0), &entities)]);
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    assert_eq!(region.chunk_entity_counts(0, 0).unwrap(), (2, 0));
+    assert_eq!(region.chunk_entity_counts(1, 0).unwrap(), (0, 5));
+}
+
+#[test]
+fn test_discard_if_grown() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    region
+        .recompress_region(&RecompressOptions::new(flate2::Compression::best()))
+        .unwrap();
+    let best = region.into_inner().into_inner();
+
+    // going back down to the fastest level makes most chunks larger again
+    let mut options = RecompressOptions::new(flate2::Compression::fast());
+    options.discard_if_grown = true;
+    options.touch = true;
+    let mut region = RegionFile::from_bytes(best.clone()).unwrap();
+    match region.recompress_region(&options) {
+        Err(Error::RegionGrew {
+            bytes_before,
+            bytes_after,
+        }) => assert!(bytes_after > bytes_before),
+        res => panic!("expected the region to grow, got {:?}", res),
+    }
+
+    let mut out = io::Cursor::new(Vec::new());
+    assert!(matches!(
+        region.compact(&mut out, Some(&options), CompactOrder::Index),
+        Err(Error::RegionGrew { .. })
+    ));
+
+    // every chunk and timestamp was put back
+    assert_eq!(region.into_inner().into_inner(), best);
+
+    // without the option the chunks that still fit their sectors are written anyway
+    options.discard_if_grown = false;
+    let mut region = RegionFile::from_bytes(best.clone()).unwrap();
+    let stats = region.recompress_region(&options).unwrap();
+    assert!(stats.bytes_after > stats.bytes_before);
+}
+
+#[test]
+fn test_chunk_iter_len() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    region.delete_chunk(0, 0).unwrap();
+
+    let mut chunks = region.chunks();
+    assert_eq!(chunks.len(), 380);
+    assert_eq!(chunks.size_hint(), (380, Some(380)));
+    assert_eq!(chunks.next(), Some((1, 0)));
+    assert_eq!(chunks.len(), 379);
+
+    // the rest still comes out in header order
+    let rest: Vec<(u8, u8)> = chunks.collect();
+    assert_eq!(rest.len(), 379);
+    assert!(rest.windows(2).all(|w| (w[0].1, w[0].0) < (w[1].1, w[1].0)));
+    assert!(rest.iter().all(|(x, z)| region.chunk_exists(*x, *z)));
+
+    let two_sectors = region
+        .chunks()
+        .filter(|(x, z)| region.get_chunk_sectors(*x, *z) == 2)
+        .count();
+    assert_eq!(two_sectors, 15);
+}
+
+#[test]
+fn test_repair() {
+    let mut data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+
+    // (14, 10) gets an unknown compression type, (0, 0) points past the end of the file and
+    // (1, 0) right into the header
+    let idx = (14 + 10 * 32) * 4;
+    let sector = u32::from_be_bytes([0, data[idx], data[idx + 1], data[idx + 2]]);
+    data[sector as usize * 4096 + 4] = 42;
+    data[..4].copy_from_slice(&(0xffff_u32 << 8 | 1).to_be_bytes());
+    data[4..8].copy_from_slice(&(1_u32 << 8 | 1).to_be_bytes());
+
+    // the chunk in the last sectors of the file (leaving out the two broken above) still loads
+    // fine, but claims more sectors than there are left
+    let last = (2..1024)
+        .max_by_key(|idx| {
+            u32::from_be_bytes([0, data[idx * 4], data[idx * 4 + 1], data[idx * 4 + 2]])
+        })
+        .unwrap();
+    data[last * 4 + 3] += 1;
+    let past_end = ((last % 32) as u8, (last / 32) as u8);
+    assert!(RegionFile::from_bytes(data.clone())
+        .unwrap()
+        .load_chunk(past_end.0, past_end.1)
+        .is_ok());
+
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    let mut expected = vec![(0, 0), (1, 0), (14, 10), past_end];
+    expected.sort_by_key(|(x, z)| (*z, *x));
+    assert_eq!(region.repair().unwrap(), expected);
+    assert_eq!(region.count_chunks(), 377);
+    assert_eq!(region.get_chunk_timestamp(14, 10), Some(0));
+    assert!(region.verify().is_empty());
+
+    // the cleared entries were written to the file as well, a second pass finds nothing
+    let data = region.into_inner().into_inner();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    assert_eq!(region.count_chunks(), 377);
+    assert!(region.repair().unwrap().is_empty());
+}
+
+#[test]
+fn test_truncated_chunk() {
+    let mut data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let idx = (14 + 10 * 32) * 4;
+    let offset = u32::from_be_bytes([0, data[idx], data[idx + 1], data[idx + 2]]) as usize * 4096;
+    let total_len = u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ]) as usize;
+
+    // the length still fits the sector, but it only covers the part of the stream that made it
+    // to disk before the sector boundary
+    let cut = total_len - 100;
+    data[offset..offset + 4].copy_from_slice(&(cut as u32).to_be_bytes());
+    for b in &mut data[offset + 4 + cut..offset + 4096] {
+        *b = 0;
+    }
+
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    let corrupt = |res: Result<(), Error>| matches!(res, Err(Error::CorruptChunk { x: 14, z: 10 }));
+    assert!(corrupt(region.load_chunk(14, 10).map(|_| ())));
+
+    let mut options = RecompressOptions::new(flate2::Compression::best());
+    assert!(corrupt(
+        region
+            .recompress_chunk_options(14, 10, &options)
+            .map(|_| ())
+    ));
+    let mut out = io::Cursor::new(Vec::new());
+    assert!(corrupt(
+        region
+            .compact(&mut out, Some(&options), CompactOrder::Index)
+            .map(|_| ())
+    ));
+
+    // nothing was written back, and when continuing on errors it's reported as failed
+    options.continue_on_error = true;
+    let stats = region.recompress_region(&options).unwrap();
+    assert_eq!(stats.failed_chunks, vec![(14, 10)]);
+    assert!(corrupt(region.load_chunk(14, 10).map(|_| ())));
+
+    let fast = flate2::Compression::fast();
+    assert!(corrupt(
+        region
+            .convert_format(CompressionFormat::Gzip, fast)
+            .map(|_| ())
+    ));
+}
+
+#[test]
+fn test_header_tables() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let region = RegionFile::from_bytes(data).unwrap();
+
+    assert_eq!(region.timestamps().len(), 1024);
+    assert_eq!(region.offsets().len(), 1024);
+
+    // (5, 3) has header entry 0x00009c01
+    let idx = 5 + 3 * 32;
+    assert_eq!(region.offsets()[idx], 0x9c * 4096);
+    assert_eq!(region.timestamps()[idx], 1383443712);
+    assert_eq!(
+        Some(region.timestamps()[idx]),
+        region.get_chunk_timestamp(5, 3)
+    );
+    assert_eq!(
+        region.offsets().iter().filter(|v| **v > 0).count(),
+        region.count_chunks()
+    );
+}
+
+#[test]
+fn test_fix_sector_counts() {
+    let mut data = region_with_chunks(&[((0, 0), b"first"), ((3, 1), b"second")]);
+
+    // (3, 1) is the last chunk, claim it has 3 sectors instead of 1
+    let idx = (3 + 32) * 4;
+    data[idx + 3] = 3;
+    data.resize(data.len() + 2 * 4096, 0);
+
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    assert_eq!(region.sector_count_mismatches().unwrap(), vec![(3, 1)]);
+    assert_eq!(region.fix_sector_counts().unwrap(), vec![(3, 1)]);
+    assert_eq!(region.get_chunk_sectors(3, 1), 1);
+    assert_eq!(region.chunk_sector_range(3, 1), Some((3, 1)));
+    assert_eq!(region.load_chunk(3, 1).unwrap(), b"second");
+
+    // the new count was written to the header as well
+    let data = region.into_inner().into_inner();
+    assert_eq!(data[idx..idx + 4], [0, 0, 3, 1]);
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    assert!(region.sector_count_mismatches().unwrap().is_empty());
+    assert!(region.fix_sector_counts().unwrap().is_empty());
+
+    // old versions of the game gave chunks that fill a sector exactly one more sector
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    assert_eq!(region.sector_count_mismatches().unwrap(), vec![(1, 10)]);
+    assert_eq!(region.fix_sector_counts().unwrap(), vec![(1, 10)]);
+    assert_eq!(region.get_chunk_sectors(1, 10), 1);
+    assert!(region.verify().is_empty());
+}
+
+#[test]
+fn test_content_digest() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let digest = RegionFile::from_bytes(data.clone())
+        .unwrap()
+        .content_digest()
+        .unwrap();
+
+    // recompressing and compacting changes every byte of the chunks, but not what's in them
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+    let options = RecompressOptions::new(flate2::Compression::best());
+    region.recompress_region(&options).unwrap();
+    assert_eq!(region.content_digest().unwrap(), digest);
+    let mut out = io::Cursor::new(Vec::new());
+    region
+        .compact(&mut out, None, CompactOrder::Morton)
+        .unwrap();
+    let mut compacted = RegionFile::from_bytes(out.into_inner()).unwrap();
+    assert_eq!(compacted.content_digest().unwrap(), digest);
+
+    // changing or removing a chunk does
+    compacted
+        .write_chunk(14, 10, b"changed", 1600000000)
+        .unwrap();
+    assert_ne!(compacted.content_digest().unwrap(), digest);
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    region.delete_chunk(0, 0).unwrap();
+    assert_ne!(region.content_digest().unwrap(), digest);
+}
+
+#[test]
+fn test_sector_size() {
+    // a region with 512 byte sectors, where the header takes up the first 16 of them
+    let mut data = vec![0; 8192];
+    let chunks: [((u8, u8), Vec<u8>); 2] =
+        [((0, 0), b"small".to_vec()), ((1, 0), incompressible(600))];
+    for ((x, z), nbt) in &chunks {
+        let compressed =
+            compress(CompressionFormat::Zlib, nbt, flate2::Compression::best()).unwrap();
+        let idx = *x as usize + *z as usize * 32;
+        let sector = data.len() / 512;
+        let sectors = (compressed.len() + 5).div_ceil(512);
+        data[idx * 4..idx * 4 + 4].copy_from_slice(&((sector << 8 | sectors) as u32).to_be_bytes());
+        data.extend_from_slice(&(compressed.len() as u32 + 1).to_be_bytes());
+        data.push(CompressionFormat::Zlib.to_byte());
+        data.extend_from_slice(&compressed);
+        data.resize((sector + sectors) * 512, 0);
+    }
+
+    let mut region = RegionFile::with_sector_size(io::Cursor::new(data.clone()), 512).unwrap();
+    assert_eq!(region.chunk_sector_range(0, 0), Some((16, 1)));
+    assert_eq!(region.chunk_sector_range(1, 0), Some((17, 2)));
+    assert_eq!(region.load_chunk(0, 0).unwrap(), b"small");
+    assert_eq!(region.load_chunk(1, 0).unwrap(), chunks[1].1);
+    assert!(region.sector_count_mismatches().unwrap().is_empty());
+    let usage = region.sector_usage().unwrap();
+    assert_eq!(usage.used_sectors, 19);
+    assert_eq!(usage.occupied.len(), 19);
+
+    // a chunk that grows is moved to the end of the file, in sectors of the same size
+    let large = incompressible(1500);
+    region.write_chunk(0, 0, &large, 1600000000).unwrap();
+    assert_eq!(region.chunk_sector_range(0, 0), Some((19, 3)));
+    assert_eq!(region.load_chunk(0, 0).unwrap(), large);
+
+    // and compacting packs them right after the header again
+    let mut out = io::Cursor::new(Vec::new());
+    region.compact(&mut out, None, CompactOrder::Index).unwrap();
+    let out = out.into_inner();
+    assert_eq!(out.len(), (16 + 3 + 2) * 512);
+    let mut compacted = RegionFile::with_sector_size(io::Cursor::new(out), 512).unwrap();
+    assert_eq!(compacted.chunk_sector_range(0, 0), Some((16, 3)));
+    assert_eq!(compacted.chunk_sector_range(1, 0), Some((19, 2)));
+    assert_eq!(compacted.load_chunk(1, 0).unwrap(), chunks[1].1);
+
+    // read with the usual sector size the offsets end up somewhere else entirely
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+    assert!(region.load_chunk(0, 0).is_err());
+
+    for sector_size in [0, 8, 1000] {
+        assert!(matches!(
+            RegionFile::with_sector_size(io::Cursor::new(data.clone()), sector_size),
+            Err(Error::Io(error)) if error.kind() == io::ErrorKind::InvalidInput
+        ));
+    }
+}
+
+#[test]
+fn test_chunks_modified_since() {
+    let mut data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+
+    // the fixture was saved over a couple of seconds, the last one has 72 chunks
+    let region = RegionFile::from_bytes(data.clone()).unwrap();
+    let changed = region.chunks_modified_since(1383443712);
+    assert_eq!(changed.len(), 72);
+    assert_eq!(changed[..3], [(14, 0), (29, 0), (30, 0)]);
+    assert!(changed
+        .iter()
+        .all(|(x, z)| region.get_chunk_timestamp(*x, *z) == Some(1383443713)));
+    assert!(region.chunks_modified_since(1383443713).is_empty());
+    assert_eq!(region.chunks_modified_since(0).len(), 381);
+
+    // a timestamp without a chunk doesn't count
+    data[14 * 4..14 * 4 + 4].copy_from_slice(&[0; 4]);
+    let region = RegionFile::from_bytes(data).unwrap();
+    assert_eq!(region.get_chunk_timestamp(14, 0), Some(1383443713));
+    assert_eq!(
+        region.chunks_modified_since(1383443712)[..2],
+        [(29, 0), (30, 0)]
+    );
+}
+
+#[test]
+fn test_zero_sector_entry() {
+    let mut data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+
+    // (0, 0) keeps its offset, but the header gives it no sectors
+    data[3] = 0;
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    assert!(region.chunk_exists(0, 0));
+    assert_eq!(region.count_chunks(), 381);
+    assert_eq!(region.get_chunk_size(0, 0), 0);
+
+    let corrupt = |res: Result<_, Error>| matches!(res, Err(Error::CorruptChunk { x: 0, z: 0 }));
+    assert!(corrupt(region.get_compression_type(0, 0).map(|_| ())));
+    assert!(corrupt(region.load_chunk(0, 0).map(|_| ())));
+    assert!(corrupt(region.junk_bytes(0, 0).map(|_| ())));
+    assert!(corrupt(region.chunk_entries().map(|_| ())));
+    assert!(corrupt(region.clean_junk().map(|_| ())));
+    assert_eq!(region.verify(), vec![(0, 0)]);
+
+    let mut options = RecompressOptions::new(flate2::Compression::fast());
+    assert!(corrupt(
+        region.recompress_chunk_options(0, 0, &options).map(|_| ())
+    ));
+    options.modified_after = Some(u32::MAX);
+    assert!(corrupt(
+        region.recompress_chunk_options(0, 0, &options).map(|_| ())
+    ));
+    options.continue_on_error = true;
+    let stats = region.recompress_chunk_options(0, 0, &options).unwrap();
+    assert_eq!(stats.failed_chunks, vec![(0, 0)]);
+
+    // it takes up no sectors, doesn't have a sector count that can be fixed and gets repaired
+    assert!(!region.sector_usage().unwrap().occupied[44]);
+    assert_eq!(
+        region.sector_count_mismatches().unwrap(),
+        vec![(0, 0), (1, 10)]
+    );
+    assert_eq!(region.fix_sector_counts().unwrap(), vec![(1, 10)]);
+    assert_eq!(region.repair().unwrap(), vec![(0, 0)]);
+    assert!(!region.chunk_exists(0, 0));
+
+    // writing a chunk in its place works as well
+    let mut data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    data[3] = 0;
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    region.write_chunk(0, 0, b"replaced", 1600000000).unwrap();
+    assert_eq!(region.load_chunk(0, 0).unwrap(), b"replaced");
+}
+
+#[test]
+fn test_compact_sectors() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+
+    // leave some holes behind, (0, 0) takes up a single sector and the first two-sector chunk two
+    let (x, z) = region
+        .chunks()
+        .find(|(x, z)| region.get_chunk_sectors(*x, *z) == 2)
+        .unwrap();
+    region.delete_chunk(0, 0).unwrap();
+    region.delete_chunk(x, z).unwrap();
+
+    let mut out = io::Cursor::new(Vec::new());
+    let stats = region.compact(&mut out, None, CompactOrder::Index).unwrap();
+    assert_eq!(stats.sectors_before, 398);
+
+    // on top of the holes, (1, 10) only needs one of its two sectors
+    assert_eq!(stats.sectors_after, 398 - 3 - 1);
+    assert_eq!(out.get_ref().len(), stats.sectors_after * 4096);
+
+    // compacting again doesn't free anything anymore
+    let mut compacted = RegionFile::from_bytes(out.into_inner()).unwrap();
+    let mut out = io::Cursor::new(Vec::new());
+    let again = compacted
+        .compact(&mut out, None, CompactOrder::Index)
+        .unwrap();
+    assert_eq!(again.sectors_before, stats.sectors_after);
+    assert_eq!(again.sectors_after, stats.sectors_after);
+}
+
+#[test]
+fn test_not_a_region_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.0.0.mca");
+    std::fs::write(&path, b"not really").unwrap();
+
+    let f = std::fs::File::open(&path).unwrap();
+    assert!(matches!(
+        RegionFile::new(f),
+        Err(Error::NotARegionFile { len: 10 })
+    ));
+    assert!(matches!(
+        RegionFile::from_bytes(Vec::new()),
+        Err(Error::NotARegionFile { len: 0 })
+    ));
+
+    // just the header is enough, and the reader doesn't have to start out at the beginning
+    let mut cursor = io::Cursor::new(vec![0; 8192]);
+    cursor.set_position(100);
+    assert_eq!(RegionFile::new(cursor).unwrap().count_chunks(), 0);
+    assert!(matches!(
+        RegionFile::from_bytes(vec![0; 8191]),
+        Err(Error::NotARegionFile { len: 8191 })
+    ));
+}
+
+#[test]
+fn test_length_past_sectors() {
+    let mut data = region_with_chunks(&[((0, 0), b"first"), ((1, 0), b"second")]);
+
+    // the first chunk now claims to run on into the second one
+    data[8192..8196].copy_from_slice(&4200u32.to_be_bytes());
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    let corrupt = |res: Result<(), Error>| matches!(res, Err(Error::CorruptChunk { x: 0, z: 0 }));
+    assert!(corrupt(region.load_chunk(0, 0).map(|_| ())));
+    assert!(corrupt(region.junk_bytes(0, 0).map(|_| ())));
+    let options = RecompressOptions::new(flate2::Compression::fast());
+    assert!(corrupt(
+        region.recompress_chunk_options(0, 0, &options).map(|_| ())
+    ));
+    assert_eq!(region.load_chunk(1, 0).unwrap(), b"second");
+
+    // reading a chunk stops at the end of its own sectors
+    region.cursor.seek(io::SeekFrom::Start(8192)).unwrap();
+    let mut read = Vec::new();
+    region.chunk_reader(0, 0, 0).read_to_end(&mut read).unwrap();
+    assert_eq!(read.len(), 4096);
+    assert_eq!(region.cursor.stream_position().unwrap(), 8192 + 4096);
+}
+
+#[test]
+fn test_recompress_region_shrink() {
+    use std::io::Write;
+
+    // random words stored without compression take 3 sectors, compressed they fit in 2
+    let mut state = 0x2545_f491u32;
+    let mut raw = Vec::new();
+    while raw.len() < 11000 {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        write!(raw, "{} ", (state >> 16) % 5000).unwrap();
+    }
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::none());
+    encoder.write_all(&raw).unwrap();
+    let stored = encoder.finish().unwrap();
+
+    let mut data = vec![0; 8192];
+    data[4 * 5..4 * 5 + 4].copy_from_slice(&(2u32 << 8 | 3).to_be_bytes());
+    data.extend_from_slice(&(stored.len() as u32 + 1).to_be_bytes());
+    data.push(2);
+    data.extend_from_slice(&stored);
+    data.resize(5 * 4096, 0xff);
+
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+    assert_eq!(region.get_chunk_sectors(5, 0), 3);
+    let options = RecompressOptions::new(flate2::Compression::best());
+    let (stats, freed) = region.recompress_region_shrink(&options).unwrap();
+    assert_eq!(stats.chunks_recompressed, 1);
+    assert!(stats.bytes_after < stats.bytes_before);
+    assert_eq!(freed, 1);
+
+    // the chunk stayed where it was, the header on disk agrees and the freed sector is zeroed
+    assert_eq!(region.chunk_sector_range(5, 0), Some((2, 2)));
+    assert_eq!(region.load_chunk(5, 0).unwrap(), raw);
+    let bytes = region.into_inner().into_inner();
+    assert_eq!(bytes.len(), 5 * 4096);
+    assert!(bytes[4 * 4096..].iter().all(|&b| b == 0));
+    let mut region = RegionFile::from_bytes(bytes).unwrap();
+    assert_eq!(region.get_chunk_sectors(5, 0), 2);
+
+    // there's nothing left to free the second time around
+    let (_, freed) = region.recompress_region_shrink(&options).unwrap();
+    assert_eq!(freed, 0);
+
+    // a chunk with a sector to spare that the options leave out keeps it
+    data[4 * 5..4 * 5 + 4].copy_from_slice(&(2u32 << 8 | 4).to_be_bytes());
+    data.resize(6 * 4096, 0xff);
+    let mut outside = options;
+    outside.chunk_range = ChunkRange::new(0, 1, 31, 31);
+    let mut unmodified = options;
+    unmodified.modified_after = Some(0);
+    for options in [outside, unmodified] {
+        let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+        let (stats, freed) = region.recompress_region_shrink(&options).unwrap();
+        assert_eq!(stats.chunks_recompressed, 0);
+        assert_eq!(freed, 0);
+        assert_eq!(region.into_inner().into_inner(), data);
+    }
+}
+
+#[test]
+fn test_header_bytes() {
+    let file = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(file.clone()).unwrap();
+    region.load_chunk(5, 3).unwrap();
+    assert_eq!(&region.header_bytes().unwrap()[..], &file[..8192]);
+}
+
+#[test]
+fn test_partial_sector_bytes() {
+    let mut data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+    assert_eq!(region.partial_sector_bytes().unwrap(), 0);
+
+    data.extend_from_slice(&[0; 17]);
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    assert_eq!(region.partial_sector_bytes().unwrap(), 17);
+
+    // the chunks themselves are still fine
+    assert!(region.load_chunk(5, 3).is_ok());
+}
+
+#[test]
+fn test_iter_raw_chunks() {
+    let file = std::fs::File::open("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(file).unwrap();
+    let chunks: Vec<(u8, u8, u8, Vec<u8>)> =
+        region.iter_raw_chunks().collect::<Result<_, _>>().unwrap();
+    assert_eq!(chunks.len(), 381);
+    assert!(chunks
+        .iter()
+        .all(|(_, _, compression_type, _)| *compression_type == 2));
+
+    // the data is exactly the length field minus the compression type
+    let (_, _, _, data) = chunks.iter().find(|c| (c.0, c.1) == (1, 10)).unwrap();
+    assert_eq!(data.len(), 4091);
+    let coords: Vec<(u8, u8)> = chunks.iter().map(|c| (c.0, c.1)).collect();
+    assert_eq!(coords, region.chunks().collect::<Vec<_>>());
+
+    let (x, z, _, data) = &chunks[0];
+    let mut decoder = flate2::read::ZlibDecoder::new(&data[..]);
+    let mut raw = Vec::new();
+    io::Read::read_to_end(&mut decoder, &mut raw).unwrap();
+    assert_eq!(raw, region.load_chunk(*x, *z).unwrap());
+}
+
+#[test]
+fn test_target_format() {
+    let data = region_with_chunks(&[((0, 0), b"first"), ((1, 0), b"second")]);
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    let level = flate2::Compression::best();
+    region
+        .convert_format(CompressionFormat::Gzip, level)
+        .unwrap();
+    region.write_chunk(2, 0, b"third", 1).unwrap();
+    let mixed = region.into_inner().into_inner();
+    let formats = |region: &mut RegionFile<io::Cursor<Vec<u8>>>| -> Vec<CompressionFormat> {
+        (0..3)
+            .map(|x| region.get_compression_type(x, 0).unwrap())
+            .collect()
+    };
+
+    // without a target every chunk keeps its own format
+    let mut region = RegionFile::from_bytes(mixed.clone()).unwrap();
+    let mut options = RecompressOptions::new(level);
+    region.recompress_region(&options).unwrap();
+    use CompressionFormat::{Gzip, Zlib};
+    assert_eq!(formats(&mut region), vec![Gzip, Gzip, Zlib]);
+
+    options.target_format = Some(Zlib);
+    let mut region = RegionFile::from_bytes(mixed.clone()).unwrap();
+    let stats = region.recompress_region(&options).unwrap();
+    assert_eq!(stats.chunks_recompressed, 2);
+    assert_eq!(formats(&mut region), vec![Zlib, Zlib, Zlib]);
+    assert_eq!(region.load_chunk(0, 0).unwrap(), b"first");
+    assert_eq!(region.load_chunk(1, 0).unwrap(), b"second");
+    assert_eq!(region.load_chunk(2, 0).unwrap(), b"third");
+
+    // compacting converts them along the way as well
+    let mut region = RegionFile::from_bytes(mixed).unwrap();
+    options.target_format = Some(CompressionFormat::Uncompressed);
+    let mut out = io::Cursor::new(Vec::new());
+    region
+        .compact(&mut out, Some(&options), CompactOrder::Index)
+        .unwrap();
+    let mut region = RegionFile::from_bytes(out.into_inner()).unwrap();
+    assert_eq!(
+        formats(&mut region),
+        vec![CompressionFormat::Uncompressed; 3]
+    );
+    assert_eq!(region.load_chunk(1, 0).unwrap(), b"second");
+}
+
+#[test]
+fn test_chunk_range() {
+    assert_eq!(ChunkRange::new(0, 0, 31, 31), Some(ChunkRange::ALL));
+    assert_eq!(ChunkRange::new(0, 0, 32, 7), None);
+    assert_eq!(ChunkRange::new(8, 0, 7, 7), None);
+    let range = ChunkRange::new(2, 3, 4, 5).unwrap();
+    assert!(range.contains(2, 3) && range.contains(4, 5) && range.contains(3, 4));
+    assert!(!range.contains(1, 3) && !range.contains(5, 5) && !range.contains(2, 6));
+    assert_eq!(range.coords().count(), 9);
+    assert!(range.coords().all(|(x, z)| range.contains(x, z)));
+
+    // only the chunks in the range get recompressed, the rest is left exactly as it was
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+    let range = ChunkRange::new(0, 0, 7, 7).unwrap();
+    let inside = region
+        .chunks()
+        .filter(|(x, z)| range.contains(*x, *z))
+        .count();
+    let mut options = RecompressOptions::new(flate2::Compression::best());
+    options.chunk_range = Some(range);
+    let mut seen = Vec::new();
+    let stats = region
+        .recompress_region_with_progress(&options, |x, z, _, _| seen.push((x, z)))
+        .unwrap();
+    assert_eq!(seen.len(), inside);
+    assert!(seen.iter().all(|(x, z)| range.contains(*x, *z)));
+    assert_eq!(stats.chunks_recompressed + stats.chunks_skipped, inside);
+    assert!(stats.chunks_recompressed > 0);
+
+    let mut original = RegionFile::from_bytes(data).unwrap();
+    let chunks: Vec<(u8, u8)> = region.chunks().collect();
+    for (x, z) in chunks {
+        if !range.contains(x, z) {
+            assert_eq!(
+                region.chunk_snapshot(x, z).unwrap(),
+                original.chunk_snapshot(x, z).unwrap()
+            );
+        }
+    }
+
+    // cleaning and verifying stick to the range as well
+    assert!(region.verify_in(range).is_empty());
+    let range = ChunkRange::new(24, 0, 31, 7).unwrap();
+    let junk = original.junk_map().unwrap();
+    assert!(junk.iter().any(|((x, z), _)| range.contains(*x, *z)));
+    assert!(junk.iter().any(|((x, z), _)| !range.contains(*x, *z)));
+    assert!(original.clean_junk_in(range, None).unwrap().chunks_cleaned > 0);
+    let left: Vec<(u8, u8)> = original
+        .junk_map()
+        .unwrap()
+        .into_iter()
+        .map(|(c, _)| c)
+        .collect();
+    let expected: Vec<(u8, u8)> = junk
+        .into_iter()
+        .map(|(c, _)| c)
+        .filter(|(x, z)| !range.contains(*x, *z))
+        .collect();
+    assert_eq!(left, expected);
 }
@@ -0,0 +1,84 @@
+use crate::region::{Error, RegionFile};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Finds the region file a chunk is in from its coordinates in the world, returns the name of the
+/// region file and the coordinates of the chunk within it
+pub fn region_for_chunk(chunk_x: i32, chunk_z: i32) -> (String, u8, u8) {
+    let name = format!(
+        "r.{}.{}.mca",
+        chunk_x.div_euclid(32),
+        chunk_z.div_euclid(32)
+    );
+    (
+        name,
+        chunk_x.rem_euclid(32) as u8,
+        chunk_z.rem_euclid(32) as u8,
+    )
+}
+
+/// A directory of region files, like the `region` folder of a world, to load chunks from by their
+/// coordinates in the world
+pub struct World {
+    dir: PathBuf,
+}
+
+impl World {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> World {
+        World { dir: dir.into() }
+    }
+
+    /// The directory the region files are in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Loads the decompressed data of the chunk at these world coordinates, from whichever region
+    /// file it is in.  A region file that doesn't exist results in an `Error::Io`.
+    pub fn load_chunk_global(&self, chunk_x: i32, chunk_z: i32) -> Result<Vec<u8>, Error> {
+        let (name, x, z) = region_for_chunk(chunk_x, chunk_z);
+        let path = self.dir.join(name);
+
+        let mut region = RegionFile::new(File::open(&path)?)?;
+        region.set_region_path(&path);
+        region.load_chunk(x, z)
+    }
+}
+
+#[test]
+fn test_region_for_chunk() {
+    assert_eq!(region_for_chunk(0, 0), ("r.0.0.mca".to_string(), 0, 0));
+    assert_eq!(region_for_chunk(31, 32), ("r.0.1.mca".to_string(), 31, 0));
+    assert_eq!(
+        region_for_chunk(1045, -312),
+        ("r.32.-10.mca".to_string(), 21, 8)
+    );
+    assert_eq!(
+        region_for_chunk(-1, -32),
+        ("r.-1.-1.mca".to_string(), 31, 0)
+    );
+    assert_eq!(
+        region_for_chunk(-33, -64),
+        ("r.-2.-2.mca".to_string(), 31, 0)
+    );
+}
+
+#[test]
+fn test_load_chunk_global() {
+    let world = World::new("tests/data");
+    let f = File::open("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::new(f).unwrap();
+
+    assert_eq!(
+        world.load_chunk_global(14, 10).unwrap(),
+        region.load_chunk(14, 10).unwrap()
+    );
+    assert!(matches!(
+        world.load_chunk_global(15, 15),
+        Err(Error::MissingChunk { x: 15, z: 15 })
+    ));
+    assert!(matches!(
+        world.load_chunk_global(-18, 10),
+        Err(Error::Io(_))
+    ));
+}
@@ -0,0 +1,140 @@
+use mccompress::{RecompressOptions, RegionFile};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Counts every allocation made in this test binary, along with the most bytes that were ever
+/// allocated at once
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// The counters are shared, so the tests take turns
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn grow(bytes: usize) {
+    let now = ALLOCATED.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    PEAK.fetch_max(now, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        grow(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        if new_size > layout.size() {
+            grow(new_size - layout.size());
+        } else {
+            ALLOCATED.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[test]
+fn test_recompress_region_reuses_buffers() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let options = RecompressOptions::new(flate2::Compression::best());
+
+    // recompressing chunk by chunk starts every chunk with a fresh buffer
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+    let chunks: Vec<(u8, u8)> = region.chunks().collect();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for (x, z) in chunks {
+        region
+            .recompress_chunk_adaptive(x, z, &options, &[options.level])
+            .unwrap();
+    }
+    let per_chunk = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    let expected = region.into_inner().into_inner();
+
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    region.recompress_region(&options).unwrap();
+    let whole_region = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    assert_eq!(region.into_inner().into_inner(), expected);
+    assert!(
+        whole_region < per_chunk,
+        "{} allocations for the region, {} chunk by chunk",
+        whole_region,
+        per_chunk
+    );
+}
+
+#[test]
+fn test_recompress_chunk_streams_payload() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+
+    // a megabyte of random words, which compresses a bit better at the best level than the default
+    let mut state = 0x2545_f491u32;
+    let mut raw = Vec::new();
+    while raw.len() < 1 << 20 {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        write!(raw, "{} ", (state >> 16) % 5000).unwrap();
+    }
+    let level = flate2::Compression::best();
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), level);
+    encoder.write_all(&raw).unwrap();
+    let expected = encoder.finish().unwrap();
+
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    region.write_chunk(3, 7, &raw, 1).unwrap();
+    let (sector, _) = region.chunk_sector_range(3, 7).unwrap();
+
+    // which is what `write_chunk` stored it as
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&raw).unwrap();
+    let compressed = encoder.finish().unwrap();
+    drop(raw);
+
+    // what decoding and encoding it takes by itself, with the payload already in memory
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), level);
+    std::io::copy(
+        &mut flate2::read::ZlibDecoder::new(&compressed[..]),
+        &mut encoder,
+    )
+    .unwrap();
+    drop(encoder.finish().unwrap());
+    let codec = PEAK.load(Ordering::Relaxed) - before;
+    drop(compressed);
+
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let stats = region.recompress_chunk(3, 7, level).unwrap();
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+    assert_eq!(stats.chunks_recompressed, 1);
+    assert_eq!(stats.bytes_after, expected.len() + 1);
+
+    // reading the compressed data into memory first would add all of it on top
+    assert!(
+        peak < codec + stats.bytes_before / 2,
+        "peaked at {} bytes for a chunk of {} bytes, the codec alone takes {}",
+        peak,
+        stats.bytes_before,
+        codec
+    );
+
+    let start = sector as usize * 4096 + 5;
+    let bytes = region.into_inner().into_inner();
+    assert_eq!(&bytes[start..start + expected.len()], &expected[..]);
+}
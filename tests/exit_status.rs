@@ -0,0 +1,24 @@
+#![cfg(feature = "cli")]
+
+use std::process::Command;
+
+#[test]
+fn test_walk_errors_fail_the_run() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::copy("tests/data/r.0.0.mca", dir.path().join("r.0.0.mca")).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_mccompress"))
+        .arg("verify")
+        .arg(dir.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let status = Command::new(env!("CARGO_BIN_EXE_mccompress"))
+        .arg("verify")
+        .arg(dir.path())
+        .arg(dir.path().join("missing"))
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(1));
+}
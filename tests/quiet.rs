@@ -0,0 +1,43 @@
+use mccompress::RecompressOptions;
+use std::process::Command;
+
+const CHILD: &str = "MCCOMPRESS_QUIET_CHILD";
+
+/// Does the actual work when started by `test_library_prints_nothing`, between two markers so
+/// anything printed in between stands out from the output of the test harness
+#[test]
+fn quiet_child() {
+    let dir = match std::env::var_os(CHILD) {
+        Some(dir) => dir,
+        None => return,
+    };
+    let path = std::path::Path::new(&dir).join("r.0.0.mca");
+    std::fs::copy("tests/data/r.0.0.mca", &path).unwrap();
+
+    println!("BEGIN");
+    let report = mccompress::recompress_paths(
+        vec![path],
+        &RecompressOptions::new(flate2::Compression::new(1)),
+        1,
+    );
+    println!("END");
+    assert!(report.errors.is_empty());
+    assert!(report.stats.chunks_recompressed > 0);
+}
+
+#[test]
+fn test_library_prints_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "quiet_child", "--nocapture", "--test-threads=1"])
+        .env(CHILD, dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let start = stdout.find("BEGIN\n").unwrap() + "BEGIN\n".len();
+    let end = stdout.find("END\n").unwrap();
+    assert_eq!(&stdout[start..end], "");
+    assert!(output.stderr.is_empty());
+}
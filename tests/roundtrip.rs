@@ -0,0 +1,54 @@
+use mccompress::{CompactOrder, RecompressOptions, RegionFile};
+
+/// Decompresses every chunk in the region, sorted by their coordinates
+fn decoded_chunks(region: &mut RegionFile<std::io::Cursor<Vec<u8>>>) -> Vec<((u8, u8), Vec<u8>)> {
+    let mut chunks: Vec<(u8, u8)> = region.chunks().collect();
+    chunks.sort();
+    chunks
+        .into_iter()
+        .map(|(x, z)| ((x, z), region.load_chunk(x, z).unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_recompress_keeps_chunk_contents() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data.clone()).unwrap();
+    let before = decoded_chunks(&mut region);
+    assert_eq!(before.len(), 381);
+
+    let mut options = RecompressOptions::new(flate2::Compression::best());
+    options.verify = true;
+    let stats = region.recompress_region(&options).unwrap();
+    assert!(stats.chunks_recompressed > 0);
+    assert_eq!(decoded_chunks(&mut region), before);
+
+    // compacting recompresses into a new file, which has to hold the same chunks as well
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    let mut out = std::io::Cursor::new(Vec::new());
+    options.allow_store = true;
+    region
+        .compact(&mut out, Some(&options), CompactOrder::Morton)
+        .unwrap();
+    let mut compacted = RegionFile::from_bytes(out.into_inner()).unwrap();
+    assert_eq!(decoded_chunks(&mut compacted), before);
+}
+
+/// The CRC-32 and length of the fixture after recompressing it in place at level 6.  When a change
+/// to the output is intended, rerun this test: the assert prints the new values to put here.
+/// They depend on the deflate implementation flate2 uses, which is miniz_oxide by default.
+const LEVEL_6_SNAPSHOT: (u32, usize) = (0xbdbd361c, 1630208);
+
+#[test]
+fn test_recompress_snapshot() {
+    let data = std::fs::read("tests/data/r.0.0.mca").unwrap();
+    let mut region = RegionFile::from_bytes(data).unwrap();
+    region
+        .recompress_region(&RecompressOptions::new(flate2::Compression::new(6)))
+        .unwrap();
+    let out = region.into_inner().into_inner();
+
+    let mut crc = flate2::Crc::new();
+    crc.update(&out);
+    assert_eq!((crc.sum(), out.len()), LEVEL_6_SNAPSHOT);
+}